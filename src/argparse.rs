@@ -1,7 +1,13 @@
-use crate::{text_from_editor, Args, Entry};
+use crate::{date, entry::EntryRef, Args, ConfigArgs, DateRange, DateWindow, Error, Notebook};
 use clap::{Arg, ArgMatches, Command};
+use regex::Regex;
+use std::any::Any;
 
 pub fn get_args() -> ArgMatches {
+    command().get_matches()
+}
+
+pub fn command() -> Command {
     Command::new("Notebook")
         .about("CLI utility for plaintext notetaking.")
         .subcommand_required(true)
@@ -17,15 +23,19 @@ pub fn get_args() -> ArgMatches {
             Arg::new("notebook_name")
                 .short('j')
                 .long("notebook")
-                .default_value("default")
-                .help("Specify a notebook name"),
+                .help("Specify a notebook name, defaults to the configured default"),
         )
         .subcommand(
             Command::new("list")
                 .short_flag('l')
                 .long_flag("list")
                 .about("List entries")
-                .arg(Arg::new("list").default_value("5")),
+                .arg(
+                    Arg::new("list")
+                        .help("Number of entries, defaults to the configured list length")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(tag_arg()),
         )
         .arg(
             Arg::new("verbose")
@@ -39,111 +49,315 @@ pub fn get_args() -> ArgMatches {
                 .short_flag('e')
                 .long_flag("edit")
                 .about("Edit specific entry")
-                .arg(Arg::new("edit")),
+                .arg(entry_arg("edit")),
         )
         .subcommand(
             Command::new("read")
                 .short_flag('r')
                 .long_flag("read")
                 .about("Display specific entry")
-                .arg(Arg::new("read")),
+                .arg(entry_arg("read")),
         )
         .subcommand(
             Command::new("delete")
                 .short_flag('X')
                 .long_flag("delete")
-                .about("Delete specific entry")
-                .arg(Arg::new("delete")),
+                .about("Delete specific entry, moving it to the trash")
+                .arg(entry_arg("delete"))
+                .arg(
+                    Arg::new("yes")
+                        .short('f')
+                        .long("yes")
+                        .help("Delete without asking for confirmation")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("undelete")
+                .about("Restore the most recently deleted entries")
+                .arg(
+                    Arg::new("count")
+                        .default_value("1")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("How many entries to restore"),
+                ),
         )
         .subcommand(
             Command::new("date search")
                 .short_flag('d')
                 .long_flag("date-search")
                 .about("Search for entries around a date")
-                .arg(Arg::new("datesearch")),
+                .arg(Arg::new("date").required(true))
+                .arg(
+                    Arg::new("days")
+                        .short('w')
+                        .long("days")
+                        .value_parser(clap::value_parser!(i64).range(0..))
+                        .conflicts_with("closest")
+                        .help("Show entries within this many days of the date"),
+                )
+                .arg(
+                    Arg::new("closest")
+                        .short('k')
+                        .long("closest")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Show this many entries closest to the date [default: 5]"),
+                ),
         )
         .subcommand(
             Command::new("search")
                 .short_flag('s')
                 .long_flag("search")
                 .about("Query to search, enclosed in quotations")
-                .arg(Arg::new("search"))
+                .arg(Arg::new("search").required(true))
+                .arg(tag_arg())
                 .subcommand(
                     Command::new("date")
                         .short_flag('d')
                         .long_flag("date")
-                        .about("Filter results by date range")
-                        .arg(Arg::new("entry")),
+                        .about("Filter results by date range, e.g. 2021-01-01..2021-06-01")
+                        .arg(Arg::new("range").required(true)),
                 ),
         )
+        .subcommand(
+            Command::new("notebook")
+                .about("Manage the notebooks in the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a notebook, stored in the given file")
+                        .arg(Arg::new("name").required(true))
+                        .arg(Arg::new("path").required(true)),
+                )
+                .subcommand(Command::new("list").about("List notebooks and their files"))
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a notebook from the config, leaving its file in place")
+                        .arg(Arg::new("name").required(true)),
+                )
+                .subcommand(
+                    Command::new("rename")
+                        .about("Rename a notebook")
+                        .arg(Arg::new("old").required(true))
+                        .arg(Arg::new("new").required(true)),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Work with the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("check").about("Check every notebook in the config can be used"),
+                ),
+        )
+        .subcommand(Command::new("tags").about("List tags and people, with how often they appear"))
+        .subcommand(
+            Command::new("rescore")
+                .about("Recalculate the sentiment of every entry")
+                .long_about(
+                    "Recalculate the sentiment of every entry, or remove it if sentiment is \
+                     turned off for the notebook",
+                ),
+        )
+        .arg(
+            Arg::new("recover")
+                .long("recover")
+                .help("Load a damaged notebook, moving unparseable entries to a rejects file")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("config")
                 .short('c')
                 .long("config")
                 .help("Path of config file to read"),
         )
-        .get_matches()
+        .arg(
+            Arg::new("file")
+                .long("file")
+                .conflicts_with_all(["notebook_name", "config"])
+                .help("Open a notebook file directly, without reading the config"),
+        )
 }
 
-pub fn parse_args(matches: ArgMatches, dt_format: &str) -> Args {
-    let verbose = matches.get_count("verbose");
+fn entry_arg(name: &'static str) -> Arg {
+    Arg::new(name)
+        .required(true)
+        .allow_negative_numbers(true)
+        .value_parser(str::parse::<EntryRef>)
+        .help("Index of the entry, counting back from the latest if negative, or its ID")
+}
 
-    match matches.subcommand() {
-        Some(("new", input)) => {
-            let text: String = match input.get_many::<String>("entry") {
-                Some(t) => t.fold(String::new(), |mut a, b| {
-                    a.reserve(b.len() + 1);
-                    a.push_str(b);
-                    a.push(' ');
-                    a
-                }),
+fn tag_arg() -> Arg {
+    Arg::new("tag")
+        .short('t')
+        .long("tag")
+        .help("Only include entries with this #tag or @person")
+}
 
-                None => text_from_editor(None).unwrap(),
-            };
-            let e = Entry::new(text, dt_format);
-            Args::New(e)
-        }
+/// Turns the command line into an `Args`, checking dates and patterns before
+/// the notebook is loaded.
+pub fn parse_args(matches: ArgMatches, notebook: &Notebook) -> Result<Args, Error> {
+    let dt_format = &notebook.dt_format;
+    let verbose = optional(&matches, "verbose")?.unwrap_or(0);
+
+    let args = match matches.subcommand() {
+        // The editor is only opened once the notebook is locked
+        Some(("new", input)) => Args::New(optional(input, "entry")?),
 
         Some(("list", input)) => {
-            let n: usize = input.get_one::<String>("list").unwrap().parse().unwrap();
-            Args::List(n, verbose)
+            let n = optional(input, "list")?.unwrap_or(notebook.list_length);
+            Args::List(n, verbose, optional(input, "tag")?)
         }
 
-        Some(("read", input)) => {
-            let n: usize = input.get_one::<String>("read").unwrap().parse().unwrap();
-            Args::Read(n)
-        }
+        Some(("read", input)) => Args::Read(value(input, "read")?),
 
-        Some(("edit", input)) => {
-            let n: usize = input.get_one::<String>("edit").unwrap().parse().unwrap();
-            Args::Edit(n)
-        }
+        Some(("edit", input)) => Args::Edit(value(input, "edit")?),
 
         Some(("delete", input)) => {
-            let n: usize = input.get_one::<String>("delete").unwrap().parse().unwrap();
-            Args::Delete(n, true)
+            let n: EntryRef = value(input, "delete")?;
+            Args::Delete(n, !optional(input, "yes")?.unwrap_or(false))
         }
 
+        Some(("undelete", input)) => Args::Undelete(value(input, "count")?),
+
         Some(("search", input)) => {
-            let mut q = String::new();
-            let search_command = input.subcommand().unwrap_or(("search", input));
-            match search_command {
-                ("date", _sub_matches) => Args::DateFilter(q),
-                ("search", _sub_matches) => {
-                    q = input.get_one::<String>("search").unwrap().into();
-                    Args::Search(q)
+            let q: String = value(input, "search")?;
+            if let Err(source) = Regex::new(&q) {
+                return Err(Error::Pattern { pattern: q, source });
+            }
+            let tag = optional(input, "tag")?;
+            match input.subcommand() {
+                Some(("date", sub_matches)) => {
+                    let r: DateRange = value::<String>(sub_matches, "range")?.parse()?;
+                    Args::DateFilter(q, r, tag)
                 }
-                (name, _) => {
-                    unreachable!("Unsupported subcommand `{name}`")
+                None => Args::Search(q, tag),
+                Some((name, _)) => {
+                    return Err(Error::Usage(format!("unknown search command '{name}'")))
                 }
             }
         }
 
         Some(("date search", input)) => {
-            let date = input.get_one::<String>("search").unwrap().into();
-            Args::DateSearch(date)
+            let date = date::parse_date(&value::<String>(input, "date")?, dt_format)?;
+            let window = match (optional(input, "days")?, optional(input, "closest")?) {
+                (Some(d), _) => DateWindow::Days(d),
+                (_, Some(k)) => DateWindow::Closest(k),
+                _ => DateWindow::default(),
+            };
+            Args::DateSearch(date, window, verbose)
         }
 
-        _ => unreachable!(),
+        Some(("tags", _)) => Args::Tags,
+
+        Some(("rescore", _)) => Args::Rescore,
+
+        Some((name, _)) => return Err(Error::Usage(format!("unknown command '{name}'"))),
+        None => return Err(Error::Usage("no command given".into())),
+    };
+
+    Ok(args)
+}
+
+/// Picks out commands that manage the config file, which are run without
+/// loading a notebook.
+pub fn parse_config_args(matches: &ArgMatches) -> Result<Option<ConfigArgs>, Error> {
+    let input = match matches.subcommand() {
+        Some(("notebook", input)) => input,
+        Some(("config", input)) => {
+            return match input.subcommand() {
+                Some(("check", _)) => Ok(Some(ConfigArgs::Check)),
+                Some((name, _)) => Err(Error::Usage(format!("unknown config command '{name}'"))),
+                None => Err(Error::Usage("no config command given".into())),
+            };
+        }
+        _ => return Ok(None),
+    };
+
+    let args = match input.subcommand() {
+        Some(("add", sub)) => ConfigArgs::AddNotebook(value(sub, "name")?, value(sub, "path")?),
+        Some(("list", _)) => ConfigArgs::ListNotebooks,
+        Some(("remove", sub)) => ConfigArgs::RemoveNotebook(value(sub, "name")?),
+        Some(("rename", sub)) => ConfigArgs::RenameNotebook(value(sub, "old")?, value(sub, "new")?),
+        Some((name, _)) => return Err(Error::Usage(format!("unknown notebook command '{name}'"))),
+        None => return Err(Error::Usage("no notebook command given".into())),
+    };
+
+    Ok(Some(args))
+}
+
+/// The value of an argument that is required or has a default.
+fn value<T: Any + Clone + Send + Sync>(matches: &ArgMatches, id: &str) -> Result<T, Error> {
+    optional(matches, id)?.ok_or_else(|| Error::Usage(format!("missing argument '{id}'")))
+}
+
+/// Like `ArgMatches::get_one`, but an error rather than a panic if the
+/// matches came from some other command.
+fn optional<T: Any + Clone + Send + Sync>(
+    matches: &ArgMatches,
+    id: &str,
+) -> Result<Option<T>, Error> {
+    matches
+        .try_get_one::<T>(id)
+        .map(Option::<&T>::cloned)
+        .map_err(|e| Error::Usage(format!("invalid argument '{id}': {e}")))
+}
+
+#[cfg(test)]
+mod test_argparse {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, Error> {
+        let mut nb = Notebook::new();
+        nb.dt_format = "%Y-%m-%d %H:%M".into();
+        parse_args(command().try_get_matches_from(args).unwrap(), &nb)
+    }
+
+    #[test]
+    fn test_parse_entry_refs() {
+        assert!(matches!(
+            parse(&["nb", "-r", "-1"]),
+            Ok(Args::Read(EntryRef::FromEnd(1)))
+        ));
+        assert!(matches!(
+            parse(&["nb", "-X", "c0ffee", "--yes"]),
+            Ok(Args::Delete(EntryRef::Id(_), false))
+        ));
+        assert!(command().try_get_matches_from(["nb", "-e", "x y"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_input() {
+        assert!(matches!(
+            parse(&["nb", "-s", "(unclosed"]),
+            Err(Error::Pattern { .. })
+        ));
+        assert!(matches!(
+            parse(&["nb", "-d", "someday"]),
+            Err(Error::Date(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_config_args() {
+        let parse = |args: &[&str]| parse_config_args(&command().get_matches_from(args));
+        assert_eq!(
+            parse(&["nb", "notebook", "rename", "work", "job"]).unwrap(),
+            Some(ConfigArgs::RenameNotebook("work".into(), "job".into()))
+        );
+        assert_eq!(
+            parse(&["nb", "config", "check"]).unwrap(),
+            Some(ConfigArgs::Check)
+        );
+        assert_eq!(parse(&["nb", "-l"]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_foreign_matches() {
+        let matches = Command::new("other")
+            .subcommand(Command::new("list"))
+            .get_matches_from(["other", "list"]);
+        let err = parse_args(matches, &Notebook::new()).unwrap_err();
+        assert!(matches!(err, Error::Usage(_)));
     }
 }
@@ -1,69 +1,564 @@
-use crate::Notebook;
+use crate::{
+    crypto::{self, EncryptionScheme, Passphrase},
+    entry::{Entry, DEFAULT_DT_FORMAT},
+    error::{Context, Error},
+    find_editor,
+    notebook::DEFAULT_LIST_LENGTH,
+    storage::StorageKind,
+    ConfigArgs, EditorError, Notebook,
+};
+use ansi_term::Style;
 use directories::{BaseDirs, UserDirs};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, fs::OpenOptions, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    fs::OpenOptions,
+    io,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+pub const CONFIG_VAR: &str = "NOTEBOOK_RS_CONFIG";
+pub const NOTEBOOK_VAR: &str = "NOTEBOOK_RS_NOTEBOOK";
+pub const FILE_VAR: &str = "NOTEBOOK_RS_FILE";
+pub const DT_FORMAT_VAR: &str = "NOTEBOOK_RS_DT_FORMAT";
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(default)]
 struct NotebookCfg {
-    notebooks: HashMap<String, Notebook>,
+    defaults: Defaults,
+    notebooks: BTreeMap<String, NotebookSettings>,
+    /// Directory of the config file, which relative paths are resolved
+    /// against.
+    #[serde(skip)]
+    dir: Option<PathBuf>,
+}
+
+/// Settings shared by every notebook, unless a notebook sets its own.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Defaults {
+    /// The notebook used when `-j` isn't given.
+    notebook: Option<String>,
+    editor: Option<String>,
+    dt_format: Option<String>,
+    sentiment: Option<bool>,
+    list_length: Option<usize>,
+    colour: Option<bool>,
+    encryption: Option<EncryptionScheme>,
+}
+
+/// A `[notebooks.<name>]` table, where anything left out is taken from the
+/// defaults.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct NotebookSettings {
+    file: String,
+    /// How entries are kept in `file`, one file by default.
+    storage: Option<StorageKind>,
+    dt_format: Option<String>,
+    sentiment: Option<bool>,
+    editor: Option<String>,
+    list_length: Option<usize>,
+    colour: Option<bool>,
+    #[serde(default)]
+    recover: bool,
+    encryption: Option<EncryptionScheme>,
+}
+
+/// Settings from the command line or the environment, which take precedence
+/// over anything in the config file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overrides {
+    /// Path of the config file, instead of the default location.
+    pub config: Option<String>,
+    /// Name of the notebook, instead of the configured default.
+    pub notebook: Option<String>,
+    /// Path of the notebook file, instead of the configured one.
+    pub file: Option<String>,
+    pub dt_format: Option<String>,
+}
+
+impl Overrides {
+    /// Reads `NOTEBOOK_RS_CONFIG`, `NOTEBOOK_RS_NOTEBOOK`, `NOTEBOOK_RS_FILE`
+    /// and `NOTEBOOK_RS_DT_FORMAT`, ignoring any that are empty.
+    pub fn from_env() -> Overrides {
+        Overrides::from_vars(|v| env::var(v).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Overrides {
+        let var = |v| var(v).filter(|s| !s.is_empty());
+        Overrides {
+            config: var(CONFIG_VAR),
+            notebook: var(NOTEBOOK_VAR),
+            file: var(FILE_VAR),
+            dt_format: var(DT_FORMAT_VAR),
+        }
+    }
+
+    /// Applies the overrides to a notebook. A file is expanded like a
+    /// configured one, but like `--file` is relative to the working
+    /// directory.
+    fn apply(&self, nb: &mut Notebook) -> Result<(), Error> {
+        if let Some(file) = &self.file {
+            nb.file = expand_home(file)?;
+            detect_directory(nb);
+        }
+        if let Some(dt_format) = &self.dt_format {
+            nb.dt_format = dt_format.clone();
+        }
+        Ok(())
+    }
 }
 
 impl std::default::Default for NotebookCfg {
     fn default() -> Self {
-        let nb_path = get_documents_dir().join("notebook.md");
-        let mut j = HashMap::new();
-        let mut nb = Notebook::new();
-        nb.file = nb_path.to_str().unwrap().into();
-        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        let nb_path = get_documents_dir().unwrap_or_default().join("notebook.md");
+        let mut j = BTreeMap::new();
+        let nb = NotebookSettings {
+            file: nb_path.to_string_lossy().into(),
+            ..Default::default()
+        };
         j.insert("default".to_string(), nb);
-        Self { notebooks: j }
+        let defaults = Defaults {
+            dt_format: Some(DEFAULT_DT_FORMAT.into()),
+            ..Default::default()
+        };
+        Self {
+            defaults,
+            notebooks: j,
+            dir: None,
+        }
     }
 }
 
-pub fn get_documents_dir() -> PathBuf {
-    // If XDG_DOCUMENT_DIR does not exist, returns XDG_HOME
-    let base_dirs = BaseDirs::new().unwrap();
+/// The user's documents dir, or their home dir if there isn't one. `None` if
+/// there isn't a home dir either.
+pub fn get_documents_dir() -> Option<PathBuf> {
+    let base_dirs = BaseDirs::new()?;
     let home_dir = base_dirs.home_dir();
-    let user_dirs = UserDirs::new().unwrap();
-    let document_dir = user_dirs.document_dir().unwrap_or(home_dir);
-    PathBuf::from(document_dir)
+    let user_dirs = UserDirs::new();
+    let document_dir = user_dirs
+        .as_ref()
+        .and_then(|u| u.document_dir())
+        .unwrap_or(home_dir);
+    Some(PathBuf::from(document_dir))
 }
 
-pub fn read_config(
-    notebook: &String,
+impl NotebookCfg {
+    fn load(conf: Option<&String>) -> Result<NotebookCfg, Error> {
+        let path = match conf {
+            Some(p) => PathBuf::from(p),
+            None => confy::get_configuration_file_path("notebook_rs", "notebook_rs.toml")?,
+        };
+        let mut config_file: NotebookCfg = confy::load_path(&path)?;
+        config_file.dir = path.parent().map(Path::to_path_buf);
+        Ok(config_file)
+    }
+
+    fn store(self, conf: Option<&String>) -> Result<(), Error> {
+        match conf {
+            Some(p) => confy::store_path(p, self)?,
+            None => confy::store("notebook_rs", "notebook_rs.toml", self)?,
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<&NotebookSettings, Error> {
+        self.notebooks.get(name).ok_or_else(|| self.unknown(name))
+    }
+
+    /// Builds the notebook called `name`, or the default one, from its own
+    /// settings and the defaults.
+    fn notebook(&self, name: Option<&str>) -> Result<Notebook, Error> {
+        self.build(self.get(name.unwrap_or(self.default_name()))?)
+    }
+
+    /// Name of the notebook used when none is given.
+    fn default_name(&self) -> &str {
+        self.defaults.notebook.as_deref().unwrap_or("default")
+    }
+
+    fn build(&self, settings: &NotebookSettings) -> Result<Notebook, Error> {
+        let mut nb = merge(settings, &self.defaults);
+        nb.file = self.resolve(&settings.file)?;
+        Ok(nb)
+    }
+
+    /// Expands `~` and variables in a notebook's `file`, and makes it
+    /// relative to the config file rather than the working directory.
+    fn resolve(&self, file: &str) -> Result<String, Error> {
+        let path = PathBuf::from(expand_home(file)?);
+        Ok(match &self.dir {
+            Some(dir) if path.is_relative() => dir.join(path).to_string_lossy().into(),
+            _ => path.to_string_lossy().into(),
+        })
+    }
+
+    fn unknown(&self, name: &str) -> Error {
+        Error::UnknownNotebook {
+            name: name.to_owned(),
+            known: self.notebooks.keys().cloned().collect(),
+        }
+    }
+}
+
+/// Expands `path` with the user's home directory and environment.
+fn expand_home(path: &str) -> Result<String, Error> {
+    let home = BaseDirs::new().map(|b| b.home_dir().to_path_buf());
+    expand_path(path, |v| env::var(v).ok(), home.as_deref())
+}
+
+/// Replaces a leading `~` with the home directory, and `$VAR` or `${VAR}`
+/// with the variable's value. A `$` not followed by a name is left alone.
+fn expand_path(
+    path: &str,
+    var: impl Fn(&str) -> Option<String>,
+    home: Option<&Path>,
+) -> Result<String, Error> {
+    let unset = |name: &str| Error::UnsetVariable {
+        path: path.to_owned(),
+        var: name.to_owned(),
+    };
+    let mut expanded = String::new();
+
+    let mut rest = match path.strip_prefix('~') {
+        Some(r) if r.is_empty() || r.starts_with(std::path::is_separator) => {
+            let home = home.ok_or_else(|| unset("HOME"))?;
+            expanded.push_str(&home.to_string_lossy());
+            r
+        }
+        _ => path,
+    };
+
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => braced
+                .find('}')
+                .map_or(("", 0), |end| (&braced[..end], end + 2)),
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&var(name).ok_or_else(|| unset(name))?);
+        }
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Builds a notebook from its own settings, falling back to the defaults and
+/// then to the built in defaults.
+fn merge(settings: &NotebookSettings, defaults: &Defaults) -> Notebook {
+    let mut nb = Notebook::new();
+    nb.file = settings.file.clone();
+    nb.storage = settings.storage.unwrap_or_default();
+    nb.dt_format = settings
+        .dt_format
+        .as_ref()
+        .or(defaults.dt_format.as_ref())
+        .map_or(DEFAULT_DT_FORMAT.into(), String::from);
+    nb.sentiment = settings.sentiment.or(defaults.sentiment).unwrap_or(true);
+    nb.editor = settings.editor.clone().or(defaults.editor.clone());
+    nb.list_length = settings
+        .list_length
+        .or(defaults.list_length)
+        .unwrap_or(DEFAULT_LIST_LENGTH);
+    nb.colour = settings.colour.or(defaults.colour).unwrap_or(true);
+    nb.recover = settings.recover;
+    nb.encryption = settings.encryption.clone().or(defaults.encryption.clone());
+
+    nb
+}
+
+/// Reads the notebook named in `overrides`, or the default notebook, from the
+/// config file, then applies the rest of `overrides`.
+pub fn read_config(overrides: &Overrides) -> Result<Notebook, Error> {
+    let config = NotebookCfg::load(overrides.config.as_ref())?;
+    let mut nb = config.notebook(overrides.notebook.as_deref())?;
+    overrides.apply(&mut nb)?;
+    nb.check_dt_format()?;
+    Ok(nb)
+}
+
+/// Opens `file` with the built in defaults and `overrides`, without reading
+/// a config file at all.
+pub fn open_file(file: &str, overrides: &Overrides) -> Result<Notebook, Error> {
+    let settings = NotebookSettings {
+        file: file.into(),
+        ..Default::default()
+    };
+    let mut nb = merge(&settings, &Defaults::default());
+    detect_directory(&mut nb);
+    if let Some(dt_format) = &overrides.dt_format {
+        nb.dt_format = dt_format.clone();
+    }
+    nb.check_dt_format()?;
+    Ok(nb)
+}
+
+/// Opens a notebook given as an existing directory as a directory notebook.
+fn detect_directory(nb: &mut Notebook) {
+    if Path::new(&nb.file).is_dir() {
+        nb.storage = StorageKind::Directory;
+    }
+}
+
+/// Runs a command that edits the notebooks in the config file.
+pub fn run_config_command<W: Write>(
+    cmd: ConfigArgs,
     conf: Option<&String>,
-) -> Result<Notebook, confy::ConfyError> {
-    // This should return the config file
-    let config_file: NotebookCfg = match conf {
-        Some(p) => confy::load_path(p).expect("Error reading config file."),
-        None => confy::load("notebook_rs", "notebook_rs.toml")
-            .expect("Error reading default config file."),
+    mut stdout: W,
+) -> Result<(), Error> {
+    let mut config_file = NotebookCfg::load(conf)?;
+    let output = |e| Error::Io {
+        context: "unable to write output".into(),
+        source: e,
     };
-    //let notebook_name = notebook.unwrap();
 
-    let notebook_cfg = config_file
-        .notebooks
-        .get(notebook)
-        .expect("Error parsing config - does notebook exist?")
-        .to_owned();
+    match cmd {
+        ConfigArgs::AddNotebook(name, path) => {
+            if config_file.notebooks.contains_key(&name) {
+                return Err(Error::NotebookExists(name));
+            }
+            // Stored in full, so the notebook is found from any directory
+            let path = std::path::absolute(&path).context(format!("invalid path '{path}'"))?;
+            let nb = NotebookSettings {
+                file: path.to_string_lossy().into(),
+                ..Default::default()
+            };
+            config_file.notebooks.insert(name.clone(), nb);
+            config_file.store(conf)?;
+            writeln!(stdout, "Added notebook '{name}' at '{}'", path.display()).map_err(output)?;
+        }
+        ConfigArgs::ListNotebooks => {
+            let style = match config_file.defaults.colour {
+                Some(false) => Style::new(),
+                _ => Style::new().bold(),
+            };
+            for (name, nb) in &config_file.notebooks {
+                writeln!(stdout, "{}\t{}", style.paint(name), nb.file).map_err(output)?;
+            }
+        }
+        ConfigArgs::RemoveNotebook(name) => {
+            let nb = config_file.get(&name)?.to_owned();
+            if name == config_file.default_name() {
+                return Err(Error::RemoveDefault(name));
+            }
+            config_file.notebooks.remove(&name);
+            config_file.store(conf)?;
+            writeln!(
+                stdout,
+                "Removed notebook '{name}', '{}' was left in place",
+                nb.file
+            )
+            .map_err(output)?;
+        }
+        ConfigArgs::RenameNotebook(old, new) => {
+            if config_file.notebooks.contains_key(&new) {
+                return Err(Error::NotebookExists(new));
+            }
+            let nb = config_file.get(&old)?.to_owned();
+            config_file.notebooks.remove(&old);
+            config_file.notebooks.insert(new.clone(), nb);
+            // The default follows the notebook to its new name
+            if old == config_file.default_name() {
+                config_file.defaults.notebook = Some(new.clone());
+            }
+            config_file.store(conf)?;
+            writeln!(stdout, "Renamed notebook '{old}' to '{new}'").map_err(output)?;
+        }
+        ConfigArgs::Check => return check_config(&config_file, stdout),
+    }
+
+    Ok(())
+}
+
+/// Something `nb config check` found wrong with a notebook. Errors mean the
+/// notebook can't be used as configured, warnings only limit what it can do.
+enum Problem {
+    Error(String),
+    Warning(String),
+}
+
+/// Reports on every notebook in the config, failing if any can't be used.
+fn check_config<W: Write>(config_file: &NotebookCfg, mut stdout: W) -> Result<(), Error> {
+    let mut report = vec![];
+    if let Some(name) = &config_file.defaults.notebook {
+        if !config_file.notebooks.contains_key(name) {
+            let msg = format!("the default notebook '{name}' isn't configured");
+            report.push(("[defaults]", vec![Problem::Error(msg)]));
+        }
+    }
+    for (name, settings) in &config_file.notebooks {
+        let problems = match config_file.build(settings) {
+            Ok(nb) => check_notebook(&nb),
+            Err(e) => vec![Problem::Error(e.to_string())],
+        };
+        report.push((name, problems));
+    }
+
+    let style = match config_file.defaults.colour {
+        Some(false) => Style::new(),
+        _ => Style::new().bold(),
+    };
+    let output = |e| Error::Io {
+        context: "unable to write output".into(),
+        source: e,
+    };
+    let mut errors = 0;
+    for (name, problems) in report {
+        let name = style.paint(name);
+        if problems.is_empty() {
+            writeln!(stdout, "{name}: ok").map_err(output)?;
+            continue;
+        }
+        writeln!(stdout, "{name}:").map_err(output)?;
+        for problem in problems {
+            match problem {
+                Problem::Error(msg) => {
+                    errors += 1;
+                    writeln!(stdout, "  error: {msg}").map_err(output)?;
+                }
+                Problem::Warning(msg) => writeln!(stdout, "  warning: {msg}").map_err(output)?,
+            }
+        }
+    }
 
-    Ok(notebook_cfg)
+    match errors {
+        0 => Ok(()),
+        n => Err(Error::CheckFailed(n)),
+    }
 }
 
-pub fn check_create_file(path: &String) -> Result<PathBuf, Box<dyn Error>> {
+fn check_notebook(nb: &Notebook) -> Vec<Problem> {
+    let mut problems = vec![];
+    let path = Path::new(&nb.file);
+
+    // Entries are written inside a directory notebook, rather than to it
+    let writable = match nb.storage {
+        StorageKind::File => check_writable(path),
+        StorageKind::Directory => check_writable(&path.join("entry.md")),
+    };
+    if let Err(e) = writable {
+        problems.push(Problem::Error(format!(
+            "unable to write to '{}': {e}",
+            path.display()
+        )));
+    }
+
+    if let Err(e) = Entry::check_dt_format(&nb.dt_format) {
+        problems.push(Problem::Error(format!("dt_format '{}' {e}", nb.dt_format)));
+    }
+
+    // Files are either all sealed or all plain text in a coherent notebook
+    let files = nb.storage().read().unwrap_or_default();
+    let (encrypted, plain): (Vec<_>, Vec<_>) = files
+        .into_iter()
+        .map(|(_, b)| b)
+        .filter(|b| !b.is_empty())
+        .partition(|b| crypto::is_encrypted(b));
+    match (&nb.encryption, encrypted.is_empty(), plain.is_empty()) {
+        (None, false, _) => problems.push(Problem::Warning(
+            "is encrypted but has no `encryption` table, it will stay encrypted".into(),
+        )),
+        (Some(_), _, false) => problems.push(Problem::Warning(
+            "has an `encryption` table but isn't encrypted, it will be encrypted when next written"
+                .into(),
+        )),
+        _ => (),
+    }
+
+    // Only checked when the passphrase can be had without a prompt
+    if let (Some(bytes), Ok(passphrase)) = (encrypted.first(), env::var(crypto::PASSPHRASE_VAR)) {
+        let scheme = nb.encryption.clone().unwrap_or_default();
+        if let Err(e) = crypto::decrypt(&scheme, &Passphrase::new(passphrase), bytes) {
+            problems.push(Problem::Error(format!(
+                "unable to decrypt with ${}: {e}",
+                crypto::PASSPHRASE_VAR
+            )));
+        }
+    }
+
+    if find_editor(nb.editor.as_deref()).is_none() {
+        problems.push(Problem::Warning(EditorError::NotSet.to_string()));
+    }
+
+    problems
+}
+
+/// Whether the notebook file, and the temp files it is saved through, can
+/// be written, without changing anything. Missing directories only need to
+/// be creatable.
+fn check_writable(path: &Path) -> io::Result<()> {
+    let dir = path
+        .ancestors()
+        .skip(1)
+        .map(|d| match d.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => d,
+        })
+        .find(|d| d.exists())
+        .unwrap_or(Path::new("."));
+    tempfile::Builder::new()
+        .prefix(".notebook_rs")
+        .suffix(".tmp")
+        .tempfile_in(dir)?;
+    if path.exists() {
+        OpenOptions::new().append(true).open(path)?;
+    }
+    Ok(())
+}
+
+/// Creates the notebook file, and any missing directories above it, if it
+/// doesn't exist yet.
+pub fn check_create_file(path: &String) -> Result<PathBuf, Error> {
     let p = PathBuf::from(path);
+    let context = || format!("unable to open or create '{path}'");
+    if let Some(dir) = p.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).context(context())?;
+    }
     OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(false)
-        .open(&p)?;
+        .open(&p)
+        .context(context())?;
     Ok(p)
 }
 
 #[cfg(test)]
 mod test_config {
     use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// A config file holding `contents`, in a directory that lasts as long
+    /// as the `TempDir`.
+    fn write_config(contents: &str) -> (TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = dir.path().join("config.toml");
+        fs::write(&conf, contents).unwrap();
+        (dir, conf.to_string_lossy().into())
+    }
+
+    fn overrides(conf: &str, notebook: Option<&str>) -> Overrides {
+        Overrides {
+            config: Some(conf.into()),
+            notebook: notebook.map(String::from),
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn test_check_create_file() {
@@ -71,4 +566,312 @@ mod test_config {
         let p = check_create_file(&filename).unwrap();
         assert!(p.exists());
     }
+
+    #[test]
+    fn test_unknown_notebook() {
+        let conf = String::from("data/test_config.toml");
+        let err = read_config(&overrides(&conf, Some("missing"))).unwrap_err();
+        assert!(matches!(err, Error::UnknownNotebook { ref name, .. } if name == "missing"));
+        assert_eq!(
+            err.to_string(),
+            "no notebook named 'missing', configured notebooks are: default"
+        );
+    }
+
+    #[test]
+    fn test_manage_notebooks() {
+        let (dir, conf) = write_config("[notebooks]\n");
+        let run = |cmd| {
+            let mut out = vec![];
+            run_config_command(cmd, Some(&conf), &mut out).map(|_| String::from_utf8(out).unwrap())
+        };
+
+        let path = dir.path().join("work.md");
+        let path = path.to_str().unwrap();
+        run(ConfigArgs::AddNotebook("work".into(), path.into())).unwrap();
+        let nb = read_config(&overrides(&conf, Some("work"))).unwrap();
+        assert_eq!(nb.file, path);
+        assert_eq!(nb.dt_format, DEFAULT_DT_FORMAT);
+        assert!(matches!(
+            run(ConfigArgs::AddNotebook("work".into(), "other.md".into())),
+            Err(Error::NotebookExists(_))
+        ));
+
+        run(ConfigArgs::RenameNotebook("work".into(), "job".into())).unwrap();
+        let list = run(ConfigArgs::ListNotebooks).unwrap();
+        assert_eq!(list, format!("\u{1b}[1mjob\u{1b}[0m\t{path}\n"));
+
+        // Renaming the default keeps it the default, and it can't be removed
+        run(ConfigArgs::AddNotebook("default".into(), path.into())).unwrap();
+        run(ConfigArgs::RenameNotebook("default".into(), "home".into())).unwrap();
+        assert_eq!(read_config(&overrides(&conf, None)).unwrap().file, path);
+        let err = run(ConfigArgs::RemoveNotebook("home".into())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'home' is the default notebook, set `notebook` in [defaults] to another before removing it"
+        );
+
+        run(ConfigArgs::RemoveNotebook("job".into())).unwrap();
+        let list = run(ConfigArgs::ListNotebooks).unwrap();
+        assert_eq!(list, format!("\u{1b}[1mhome\u{1b}[0m\t{path}\n"));
+        let err = run(ConfigArgs::RemoveNotebook("job".into())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no notebook named 'job', configured notebooks are: home"
+        );
+    }
+
+    #[test]
+    fn test_defaults_merge() {
+        let (dir, conf) = write_config(
+            "[defaults]
+notebook = 'work'
+editor = 'vim'
+dt_format = '%Y-%m-%d %H:%M'
+sentiment = false
+list_length = 10
+colour = false
+
+[defaults.encryption]
+
+[notebooks.work]
+file = 'work.md'
+
+[notebooks.diary]
+file = 'diary.md'
+editor = 'nano'
+sentiment = true
+list_length = 3
+",
+        );
+
+        let work = read_config(&overrides(&conf, None)).unwrap();
+        assert_eq!(work.file, dir.path().join("work.md").to_string_lossy());
+        assert_eq!(work.editor.as_deref(), Some("vim"));
+        assert_eq!(work.dt_format, "%Y-%m-%d %H:%M");
+        assert!(!work.sentiment);
+        assert_eq!(work.list_length, 10);
+        assert!(!work.colour);
+        assert_eq!(work.encryption, Some(EncryptionScheme::default()));
+
+        let diary = read_config(&overrides(&conf, Some("diary"))).unwrap();
+        assert_eq!(diary.editor.as_deref(), Some("nano"));
+        assert_eq!(diary.dt_format, "%Y-%m-%d %H:%M");
+        assert!(diary.sentiment);
+        assert_eq!(diary.list_length, 3);
+        assert!(diary.encryption.is_some());
+    }
+
+    #[test]
+    fn test_builtin_defaults() {
+        let (_dir, conf) = write_config("[notebooks.default]\nfile = 'notebook.md'\n");
+
+        let nb = read_config(&overrides(&conf, None)).unwrap();
+        assert_eq!(nb.dt_format, DEFAULT_DT_FORMAT);
+        assert!(nb.sentiment);
+        assert!(nb.editor.is_none());
+        assert_eq!(nb.list_length, DEFAULT_LIST_LENGTH);
+        assert!(nb.colour);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let (dir, conf) = write_config(
+            "[defaults]\nnotebook = 'work'\n\n[notebooks.work]\nfile = 'work.md'\neditor = 'vim'\n\n[notebooks.diary]\nfile = 'diary.md'\n",
+        );
+
+        let mut o = overrides(&conf, Some("diary"));
+        o.file = Some("other.md".into());
+        o.dt_format = Some("%Y-%m-%d".into());
+        // Relative to the working directory, like `--file`, not the config
+        let nb = read_config(&o).unwrap();
+        assert_eq!(nb.file, "other.md");
+        assert_eq!(nb.dt_format, "%Y-%m-%d");
+        assert_eq!(nb.storage, StorageKind::File);
+
+        // And taken as a directory notebook if it is one
+        o.file = Some("data".into());
+        let nb = read_config(&o).unwrap();
+        assert_eq!(nb.file, "data");
+        assert_eq!(nb.storage, StorageKind::Directory);
+
+        o.notebook = None;
+        o.file = None;
+        let nb = read_config(&o).unwrap();
+        assert_eq!(nb.file, dir.path().join("work.md").to_string_lossy());
+        assert_eq!(nb.editor.as_deref(), Some("vim"));
+    }
+
+    #[test]
+    fn test_overrides_from_vars() {
+        let o = Overrides::from_vars(|v| match v {
+            NOTEBOOK_VAR => Some("work".into()),
+            FILE_VAR => Some("".into()),
+            DT_FORMAT_VAR => Some("%Y".into()),
+            _ => None,
+        });
+        assert_eq!(
+            o,
+            Overrides {
+                notebook: Some("work".into()),
+                dt_format: Some("%Y".into()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_open_file() {
+        let o = Overrides {
+            config: Some("missing.toml".into()),
+            file: Some("ignored.md".into()),
+            dt_format: Some("%Y".into()),
+            ..Default::default()
+        };
+        let nb = open_file("notebook.md", &o).unwrap();
+        assert_eq!(nb.file, "notebook.md");
+        assert_eq!(nb.dt_format, "%Y");
+        assert_eq!(nb.list_length, DEFAULT_LIST_LENGTH);
+    }
+
+    #[test]
+    fn test_invalid_dt_format() {
+        let o = Overrides {
+            dt_format: Some("%Q".into()),
+            ..Default::default()
+        };
+        let err = open_file("notebook.md", &o).unwrap_err();
+        assert_eq!(err.to_string(), "dt_format '%Q' is not a valid format");
+    }
+
+    #[test]
+    fn test_check_config() {
+        // Paths are relative to the config file, which the bad notebook is under
+        let (dir, conf) = write_config(
+            "[defaults]
+notebook = 'missing'
+editor = 'vim'
+colour = false
+
+[notebooks.good]
+file = 'good.md'
+
+[notebooks.bad]
+file = 'config.toml/bad.md'
+dt_format = '%H:%M'
+",
+        );
+        let good = dir.path().join("good.md");
+
+        let mut out = vec![];
+        let err = run_config_command(ConfigArgs::Check, Some(&conf), &mut out).unwrap_err();
+        assert_eq!(err.to_string(), "config check found 3 problems");
+        let report = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[0], "[defaults]:");
+        assert_eq!(
+            lines[1],
+            "  error: the default notebook 'missing' isn't configured"
+        );
+        assert_eq!(lines[2], "bad:");
+        assert!(lines[3].starts_with(&format!("  error: unable to write to '{conf}/bad.md': ")));
+        assert_eq!(
+            lines[4],
+            "  error: dt_format '%H:%M' can't be read back: could not parse timestamp"
+        );
+        assert_eq!(lines[5], "good: ok");
+        assert!(!good.exists());
+    }
+
+    #[test]
+    fn test_check_encryption_mismatch() {
+        let (dir, conf) = write_config(
+            "[defaults]
+editor = 'vim'
+colour = false
+
+[notebooks.plain]
+file = 'plain.md'
+[notebooks.plain.encryption]
+
+[notebooks.sealed]
+file = 'sealed.md'
+",
+        );
+        fs::write(dir.path().join("plain.md"), "Dear diary").unwrap();
+        let scheme = EncryptionScheme::default();
+        let sealed = crypto::encrypt(&scheme, &Passphrase::new("pw".into()), b"Dear diary");
+        fs::write(dir.path().join("sealed.md"), sealed.unwrap()).unwrap();
+
+        // Warnings only, as both notebooks can still be used
+        let mut out = vec![];
+        run_config_command(ConfigArgs::Check, Some(&conf), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "plain:
+  warning: has an `encryption` table but isn't encrypted, it will be encrypted when next written
+sealed:
+  warning: is encrypted but has no `encryption` table, it will stay encrypted
+"
+        );
+    }
+
+    #[test]
+    fn test_check_create_file_reports_path() {
+        let err = check_create_file(&"data/test.md/notebook.md".into()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("unable to open or create 'data/test.md/notebook.md': "));
+    }
+
+    #[test]
+    fn test_check_create_file_creates_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal/2021/notebook.md");
+        check_create_file(&path.to_string_lossy().into()).unwrap();
+        assert!(path.is_file());
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let var = |v: &str| match v {
+            "XDG_DATA_HOME" => Some("/data".to_string()),
+            "USER" => Some("me".to_string()),
+            _ => None,
+        };
+        let home = Some(Path::new("/home/me"));
+        let expand = |p| expand_path(p, var, home).unwrap();
+
+        assert_eq!(expand("~/journal/work.md"), "/home/me/journal/work.md");
+        assert_eq!(expand("~"), "/home/me");
+        assert_eq!(expand("$XDG_DATA_HOME/nb/work.md"), "/data/nb/work.md");
+        assert_eq!(expand("/notes/${USER}_work.md"), "/notes/me_work.md");
+        assert_eq!(expand("~user/a$.md"), "~user/a$.md");
+        assert_eq!(expand("cost$/${.md"), "cost$/${.md");
+
+        let err = expand_path("$MISSING/work.md", var, home).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "notebook file '$MISSING/work.md' uses $MISSING, which isn't set"
+        );
+        assert!(matches!(
+            expand_path("~/work.md", var, None),
+            Err(Error::UnsetVariable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_relative_to_config() {
+        let (dir, conf) = write_config(
+            "[notebooks.default]\nfile = 'journal/notebook.md'\n\n[notebooks.abs]\nfile = '/notes/abs.md'\n",
+        );
+
+        let nb = read_config(&overrides(&conf, None)).unwrap();
+        assert_eq!(
+            nb.file,
+            dir.path().join("journal/notebook.md").to_string_lossy()
+        );
+        let nb = read_config(&overrides(&conf, Some("abs"))).unwrap();
+        assert_eq!(nb.file, "/notes/abs.md");
+    }
 }
@@ -0,0 +1,258 @@
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{env, error::Error, fmt, io};
+
+/// Marks the start of an encrypted notebook, followed by salt, nonce and ciphertext.
+const MAGIC: &[u8] = b"NBRSENC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+
+pub const PASSPHRASE_VAR: &str = "NOTEBOOK_RS_PASSPHRASE";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cipher {
+    #[default]
+    XChaCha20Poly1305,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kdf {
+    #[default]
+    Argon2id,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionScheme {
+    pub cipher: Cipher,
+    pub hash: Kdf,
+}
+
+#[derive(Debug)]
+pub enum CryptoError {
+    Prompt(io::Error),
+    Mismatch,
+    Kdf(argon2::Error),
+    Truncated,
+    Encrypt,
+    Decrypt,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CryptoError::Prompt(e) => write!(f, "could not read passphrase: {e}"),
+            CryptoError::Mismatch => write!(f, "passphrases do not match"),
+            CryptoError::Kdf(e) => write!(f, "could not derive key: {e}"),
+            CryptoError::Truncated => write!(f, "encrypted notebook is truncated"),
+            CryptoError::Encrypt => write!(f, "could not encrypt notebook"),
+            CryptoError::Decrypt => write!(f, "wrong passphrase or corrupted notebook"),
+        }
+    }
+}
+
+impl Error for CryptoError {}
+
+/// A passphrase held in memory, kept out of `Debug` output.
+#[derive(Clone)]
+pub struct Passphrase(String);
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Passphrase(..)")
+    }
+}
+
+impl Passphrase {
+    pub fn new(passphrase: String) -> Passphrase {
+        Passphrase(passphrase)
+    }
+
+    /// Reads the passphrase from `NOTEBOOK_RS_PASSPHRASE`, otherwise prompts
+    /// for it, twice if `confirm` is set.
+    pub fn obtain(confirm: bool) -> Result<Passphrase, CryptoError> {
+        if let Ok(p) = env::var(PASSPHRASE_VAR) {
+            return Ok(Passphrase(p));
+        }
+
+        let p = rpassword::prompt_password("Notebook passphrase: ").map_err(CryptoError::Prompt)?;
+        if confirm {
+            let again =
+                rpassword::prompt_password("Confirm passphrase: ").map_err(CryptoError::Prompt)?;
+            if p != again {
+                return Err(CryptoError::Mismatch);
+            }
+        }
+        Ok(Passphrase(p))
+    }
+}
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// A key derived from a passphrase, with the salt it was derived with.
+///
+/// Deriving a key is slow by design, so a notebook derives one and seals
+/// each of its files with it, under a fresh nonce every time.
+#[derive(Clone)]
+pub struct Key {
+    salt: [u8; SALT_LEN],
+    cipher: XChaCha20Poly1305,
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+impl Key {
+    /// Derives a key with a new random salt.
+    pub fn generate(
+        scheme: &EncryptionScheme,
+        passphrase: &Passphrase,
+    ) -> Result<Key, CryptoError> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Key::derive(scheme, passphrase, salt)
+    }
+
+    /// Derives the key `bytes` were encrypted with.
+    pub fn for_bytes(
+        scheme: &EncryptionScheme,
+        passphrase: &Passphrase,
+        bytes: &[u8],
+    ) -> Result<Key, CryptoError> {
+        Key::derive(scheme, passphrase, split(bytes)?.0)
+    }
+
+    fn derive(
+        scheme: &EncryptionScheme,
+        passphrase: &Passphrase,
+        salt: [u8; SALT_LEN],
+    ) -> Result<Key, CryptoError> {
+        let mut key = [0u8; KEY_LEN];
+        match scheme.hash {
+            Kdf::Argon2id => Argon2::default()
+                .hash_password_into(passphrase.0.as_bytes(), &salt, &mut key)
+                .map_err(CryptoError::Kdf)?,
+        }
+
+        let cipher = match scheme.cipher {
+            Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new(&key.into()),
+        };
+        Ok(Key { salt, cipher })
+    }
+
+    /// Whether `bytes` were encrypted with this key's salt, and so can be
+    /// decrypted without deriving another.
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        split(bytes).is_ok_and(|(salt, ..)| salt == self.salt)
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| CryptoError::Encrypt)?;
+
+        Ok([MAGIC, &self.salt, &nonce, &ciphertext].concat())
+    }
+
+    pub fn decrypt(&self, bytes: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (salt, nonce, ciphertext) = split(bytes)?;
+        if salt != self.salt {
+            return Err(CryptoError::Decrypt);
+        }
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptoError::Decrypt)
+    }
+}
+
+/// The salt, nonce and ciphertext of encrypted bytes.
+type Parts<'a> = ([u8; SALT_LEN], &'a [u8], &'a [u8]);
+
+fn split(bytes: &[u8]) -> Result<Parts<'_>, CryptoError> {
+    let body = bytes.strip_prefix(MAGIC).ok_or(CryptoError::Decrypt)?;
+    if body.len() < SALT_LEN + NONCE_LEN {
+        return Err(CryptoError::Truncated);
+    }
+    let (salt, rest) = body.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    Ok((salt.try_into().unwrap(), nonce, ciphertext))
+}
+
+pub fn encrypt(
+    scheme: &EncryptionScheme,
+    passphrase: &Passphrase,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    Key::generate(scheme, passphrase)?.encrypt(plaintext)
+}
+
+pub fn decrypt(
+    scheme: &EncryptionScheme,
+    passphrase: &Passphrase,
+    bytes: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    Key::for_bytes(scheme, passphrase, bytes)?.decrypt(bytes)
+}
+
+#[cfg(test)]
+mod test_crypto {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let scheme = EncryptionScheme::default();
+        let pass = Passphrase::new("correct horse".into());
+        let bytes = encrypt(&scheme, &pass, b"Dear diary").unwrap();
+        assert!(is_encrypted(&bytes));
+        assert!(!bytes.windows(10).any(|w| w == b"Dear diary"));
+        assert_eq!(decrypt(&scheme, &pass, &bytes).unwrap(), b"Dear diary");
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let scheme = EncryptionScheme::default();
+        let bytes = encrypt(&scheme, &Passphrase::new("right".into()), b"Dear diary").unwrap();
+        let err = decrypt(&scheme, &Passphrase::new("wrong".into()), &bytes).unwrap_err();
+        assert!(matches!(err, CryptoError::Decrypt));
+    }
+
+    #[test]
+    fn test_key_reuses_salt() {
+        let scheme = EncryptionScheme::default();
+        let pass = Passphrase::new("correct horse".into());
+        let key = Key::generate(&scheme, &pass).unwrap();
+        let a = key.encrypt(b"Dear diary").unwrap();
+        let b = key.encrypt(b"Dear diary").unwrap();
+
+        // Same salt, but a fresh nonce for each file
+        assert_eq!(a[..MAGIC.len() + SALT_LEN], b[..MAGIC.len() + SALT_LEN]);
+        assert_ne!(a, b);
+        assert!(key.matches(&b));
+        assert_eq!(decrypt(&scheme, &pass, &b).unwrap(), b"Dear diary");
+
+        let other = encrypt(&scheme, &pass, b"Dear diary").unwrap();
+        assert!(!key.matches(&other));
+        assert!(matches!(key.decrypt(&other), Err(CryptoError::Decrypt)));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let scheme = EncryptionScheme::default();
+        let err = decrypt(&scheme, &Passphrase::new("p".into()), b"NBRSENC1short").unwrap_err();
+        assert!(matches!(err, CryptoError::Truncated));
+    }
+}
@@ -0,0 +1,200 @@
+use chrono::{prelude::Local, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use std::{error::Error, fmt, str::FromStr};
+
+/// Date formats accepted for absolute bounds, most specific first.
+const DATETIME_FORMATS: [&str; 3] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"];
+const DATE_FORMATS: [&str; 4] = ["%Y-%m-%d", "%d/%m/%Y", "%d %B %Y", "%B %d, %Y"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DateParseError {
+    input: String,
+}
+
+impl fmt::Display for DateParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "could not parse date '{}'", self.input)
+    }
+}
+
+impl Error for DateParseError {}
+
+/// How far around a date to look for entries.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DateWindow {
+    /// Entries within this many days either side of the date.
+    Days(i64),
+    /// The given number of entries closest to the date.
+    Closest(usize),
+}
+
+impl Default for DateWindow {
+    fn default() -> Self {
+        DateWindow::Closest(5)
+    }
+}
+
+/// An inclusive range of timestamps, either end of which may be left open.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DateRange {
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+}
+
+impl DateRange {
+    pub fn contains(&self, dt: &NaiveDateTime) -> bool {
+        self.start.is_none_or(|s| s <= *dt) && self.end.is_none_or(|e| *dt <= e)
+    }
+
+    /// Parses `start..end` relative to `now`, where either side may be empty.
+    pub fn parse_from(s: &str, now: NaiveDateTime) -> Result<DateRange, DateParseError> {
+        let (start, end) = s.split_once("..").ok_or_else(|| DateParseError {
+            input: s.to_owned(),
+        })?;
+
+        let bound = |b: &str, end_of_day| match b.trim() {
+            "" => Ok(None),
+            b => parse_datetime(b, now, end_of_day).map(Some),
+        };
+
+        Ok(DateRange {
+            start: bound(start, false)?,
+            end: bound(end, true)?,
+        })
+    }
+}
+
+impl FromStr for DateRange {
+    type Err = DateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DateRange::parse_from(s, Local::now().naive_local())
+    }
+}
+
+/// Parses an absolute or relative point in time.
+///
+/// Accepts ISO dates and datetimes, `now`, `today`, `yesterday` and
+/// humantime durations such as `3 weeks ago`. Bare dates resolve to the
+/// start of the day, or to its end when `end_of_day` is set.
+pub fn parse_datetime(
+    s: &str,
+    now: NaiveDateTime,
+    end_of_day: bool,
+) -> Result<NaiveDateTime, DateParseError> {
+    let s = s.trim();
+    let day = |d: NaiveDate| {
+        if end_of_day {
+            d.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap())
+        } else {
+            d.and_time(NaiveTime::MIN)
+        }
+    };
+
+    match s.to_lowercase().as_str() {
+        "now" => return Ok(now),
+        "today" => return Ok(day(now.date())),
+        "yesterday" => return Ok(day(now.date() - Duration::days(1))),
+        _ => (),
+    }
+
+    if let Some(dt) = DATETIME_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+    {
+        return Ok(dt);
+    }
+
+    if let Some(d) = DATE_FORMATS
+        .iter()
+        .find_map(|f| NaiveDate::parse_from_str(s, f).ok())
+    {
+        return Ok(day(d));
+    }
+
+    s.strip_suffix("ago")
+        .and_then(|d| humantime::parse_duration(d.trim()).ok())
+        .and_then(|d| Duration::from_std(d).ok())
+        .and_then(|d| now.checked_sub_signed(d))
+        .ok_or_else(|| DateParseError {
+            input: s.to_owned(),
+        })
+}
+
+/// Parses a date given on the command line, trying the notebook's own
+/// `dt_format` before the formats understood by [`parse_datetime`].
+pub fn parse_date(s: &str, dt_format: &str) -> Result<NaiveDateTime, DateParseError> {
+    match NaiveDateTime::parse_from_str(s.trim(), dt_format) {
+        Ok(dt) => Ok(dt),
+        Err(_) => parse_datetime(s, Local::now().naive_local(), false),
+    }
+}
+
+#[cfg(test)]
+mod test_date {
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2021-06-01 12:00", "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn dt(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_closed_range() {
+        let r = DateRange::parse_from("2021-01-01..2021-06-01", now()).unwrap();
+        assert_eq!(r.start, Some(dt("2021-01-01 00:00:00")));
+        assert_eq!(r.end, Some(dt("2021-06-01 23:59:59")));
+    }
+
+    #[test]
+    fn test_open_ranges() {
+        let r = DateRange::parse_from("..2021-06-01", now()).unwrap();
+        assert_eq!(r.start, None);
+        assert!(r.contains(&dt("1999-01-01 00:00:00")));
+
+        let r = DateRange::parse_from("2021-01-01..", now()).unwrap();
+        assert_eq!(r.end, None);
+        assert!(!r.contains(&dt("2020-12-31 23:59:00")));
+    }
+
+    #[test]
+    fn test_relative_range() {
+        let r = DateRange::parse_from("3 weeks ago..now", now()).unwrap();
+        assert_eq!(r.start, Some(dt("2021-05-11 12:00:00")));
+        assert_eq!(r.end, Some(now()));
+    }
+
+    #[test]
+    fn test_natural_dates() {
+        assert_eq!(
+            parse_datetime("yesterday", now(), false).unwrap(),
+            dt("2021-05-31 00:00:00")
+        );
+        assert_eq!(
+            parse_datetime("13 May 2021", now(), false).unwrap(),
+            dt("2021-05-13 00:00:00")
+        );
+    }
+
+    #[test]
+    fn test_parse_date_notebook_format() {
+        assert_eq!(
+            parse_date("Thursday 13 May, 2021 - 22:17", "%A %e %B, %Y - %H:%M").unwrap(),
+            dt("2021-05-13 22:17:00")
+        );
+        assert_eq!(
+            parse_date("2021-05-13", "%A %e %B, %Y - %H:%M").unwrap(),
+            dt("2021-05-13 00:00:00")
+        );
+    }
+
+    #[test]
+    fn test_invalid_range() {
+        assert!(DateRange::parse_from("2021-01-01", now()).is_err());
+        assert!(DateRange::parse_from("last tuesday..", now()).is_err());
+        // Before the earliest representable date
+        assert!(DateRange::parse_from("1000000 years ago..", now()).is_err());
+    }
+}
@@ -1,33 +1,370 @@
-use chrono::{prelude::Local, NaiveDateTime};
-use std::{fmt, str::FromStr, string::ParseError};
+use chrono::{prelude::Local, NaiveDate, NaiveDateTime, ParseError};
+use regex::Regex;
+use std::{error::Error, fmt, str::FromStr, sync::LazyLock};
 use vader_sentiment::SentimentIntensityAnalyzer;
 
 use crate::Sentiment;
 
+pub const DEFAULT_DT_FORMAT: &str = "%A %e %B, %Y - %H:%M";
+
+/// Written on a line of its own after each entry.
+pub const TERMINATOR: &str = "¶";
+
+/// `#tag` or `@person` at the start of a word, beginning with a letter.
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|\s)([#@])(\p{L}[\w-]*)").unwrap());
+
+/// Length of the short hex IDs entries are given.
+pub const ID_LEN: usize = 6;
+
+/// Formats tried, in order, when a timestamp doesn't match the notebook's own.
+const KNOWN_DT_FORMATS: [&str; 7] = [
+    DEFAULT_DT_FORMAT,
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%d/%m/%Y %H:%M",
+    "%c",
+    "%Y-%m-%d",
+];
+
+/// Reasons an entry could not be read, with the 1-based line they occurred on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntryParseError {
+    MissingHeader { line: usize },
+    BadTimestamp { line: usize, source: ParseError },
+    BadSentiment { line: usize },
+    MissingSeparator { line: usize },
+}
+
+impl EntryParseError {
+    pub fn line(&self) -> usize {
+        match self {
+            EntryParseError::MissingHeader { line }
+            | EntryParseError::BadTimestamp { line, .. }
+            | EntryParseError::BadSentiment { line }
+            | EntryParseError::MissingSeparator { line } => *line,
+        }
+    }
+
+    /// Shifts the line number by `n`, for entries read from the middle of a file.
+    pub fn offset(mut self, n: usize) -> Self {
+        match &mut self {
+            EntryParseError::MissingHeader { line }
+            | EntryParseError::BadTimestamp { line, .. }
+            | EntryParseError::BadSentiment { line }
+            | EntryParseError::MissingSeparator { line } => *line += n,
+        }
+        self
+    }
+}
+
+impl fmt::Display for EntryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EntryParseError::MissingHeader { .. } => write!(f, "missing '###' timestamp header"),
+            EntryParseError::BadTimestamp { .. } => write!(f, "could not parse timestamp"),
+            EntryParseError::BadSentiment { .. } => write!(f, "could not parse sentiment line"),
+            EntryParseError::MissingSeparator { .. } => write!(f, "missing '---' separator"),
+        }
+    }
+}
+
+impl Error for EntryParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EntryParseError::BadTimestamp { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Why a `dt_format` can't be used to write entry headers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DtFormatError {
+    Invalid,
+    Unreadable(EntryParseError),
+    /// Headers written with the format read back as a different time.
+    Lossy {
+        written: String,
+        read: NaiveDateTime,
+    },
+}
+
+impl fmt::Display for DtFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DtFormatError::Invalid => write!(f, "is not a valid format"),
+            DtFormatError::Unreadable(e) => write!(f, "can't be read back: {e}"),
+            DtFormatError::Lossy { written, read } => {
+                write!(f, "writes '{written}', which reads back as {read}")
+            }
+        }
+    }
+}
+
+impl Error for DtFormatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DtFormatError::Unreadable(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Points at an entry, as given to `-r`, `-e` and `-X`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntryRef {
+    /// Position in the notebook, oldest first.
+    Index(usize),
+    /// Position counting back from the latest, which is `-1`.
+    FromEnd(usize),
+    Id(String),
+}
+
+impl FromStr for EntryRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{s}' is not an entry index or ID");
+
+        if let Some(n) = s.strip_prefix('-') {
+            return match n.parse() {
+                Ok(0) | Err(_) => Err(invalid()),
+                Ok(n) => Ok(EntryRef::FromEnd(n)),
+            };
+        }
+        // Short numbers are indices, anything longer may be an ID that
+        // happens to be all digits
+        if s.len() < ID_LEN {
+            if let Ok(n) = s.parse() {
+                return Ok(EntryRef::Index(n));
+            }
+        }
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            Ok(EntryRef::Id(s.to_ascii_lowercase()))
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl fmt::Display for EntryRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EntryRef::Index(n) => write!(f, "{n}"),
+            EntryRef::FromEnd(n) => write!(f, "-{n}"),
+            EntryRef::Id(id) => write!(f, "{id}"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Entry {
+    pub id: String,
     pub text: String,
     pub timestamp: NaiveDateTime,
-    sentiment: Sentiment,
+    pub tags: Vec<String>,
+    pub people: Vec<String>,
+    sentiment: Option<Sentiment>,
     dt_format: String,
 }
 
 impl Entry {
-    pub fn new(text: String, dt_fmt: &str) -> Entry {
+    /// Creates an entry timestamped now, scoring its sentiment only if asked to.
+    pub fn new(text: String, dt_fmt: &str, sentiment: bool) -> Entry {
         let dt_format = dt_fmt.to_string();
-        let score = Entry::calculate_sentiment(&text);
-        let sentiment = Sentiment::new(score);
+        let text = text.trim().to_string();
+        let sentiment = sentiment.then(|| Sentiment::new(Entry::calculate_sentiment(&text)));
+        let (tags, people) = Entry::extract_tags(&text);
+        let timestamp = Local::now().naive_local();
         Entry {
+            id: Entry::derive_id(&timestamp, &text, 0),
             text,
-            timestamp: Local::now().naive_local(),
+            timestamp,
+            tags,
+            people,
             sentiment,
             dt_format,
         }
     }
 
+    /// A short ID hashed from the timestamp and text, with `salt` to pick
+    /// another when two entries would share one. FNV-1a keeps it the same
+    /// from one build to the next.
+    pub fn derive_id(timestamp: &NaiveDateTime, text: &str, salt: u32) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let ts = timestamp.format("%Y-%m-%dT%H:%M:%S%.f").to_string();
+        for b in [ts.as_bytes(), text.as_bytes(), &salt.to_le_bytes()].concat() {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        format!("{:0width$x}", hash >> (64 - 4 * ID_LEN), width = ID_LEN)
+    }
+
+    /// Gives the entry a new ID, keeping clear of those already `taken`.
+    pub fn reassign_id(&mut self, taken: impl Fn(&str) -> bool) {
+        let mut salt = 1;
+        while taken(&self.id) {
+            self.id = Entry::derive_id(&self.timestamp, &self.text, salt);
+            salt += 1;
+        }
+    }
+
+    /// Replaces the text, rescoring its sentiment if the entry has one.
     pub fn replace_text(&mut self, text: &str) {
         self.text.clear();
-        self.text.push_str(text);
+        self.text.push_str(text.trim());
+        (self.tags, self.people) = Entry::extract_tags(&self.text);
+        self.rescore(self.sentiment.is_some());
+    }
+
+    /// Whether the entry has `tag`, given as `work`, `#work` or `@person`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let (list, name) = match tag.strip_prefix('@') {
+            Some(p) => (&self.people, p),
+            None => (&self.tags, tag.trim_start_matches('#')),
+        };
+        list.iter().any(|t| t.eq_ignore_ascii_case(name))
+    }
+
+    /// Collects the distinct `#tags` and `@people` mentioned in the text.
+    fn extract_tags(text: &str) -> (Vec<String>, Vec<String>) {
+        let (mut tags, mut people) = (Vec::<String>::new(), Vec::<String>::new());
+        for c in TAG.captures_iter(text) {
+            let list = if &c[1] == "#" { &mut tags } else { &mut people };
+            if !list.iter().any(|t| t.eq_ignore_ascii_case(&c[2])) {
+                list.push(c[2].to_string());
+            }
+        }
+        (tags, people)
+    }
+
+    /// Recalculates the sentiment score, or removes it if `sentiment` is false.
+    pub fn rescore(&mut self, sentiment: bool) {
+        self.sentiment = sentiment.then(|| Sentiment::new(Entry::calculate_sentiment(&self.text)));
+    }
+
+    /// Parses an entry written with `dt_format`, falling back to the known
+    /// formats if the timestamp doesn't match. The parsed entry takes on
+    /// `dt_format`, so it is written back in the notebook's format.
+    pub fn parse_with_format(s: &str, dt_format: &str) -> Result<Entry, EntryParseError> {
+        // (line number, byte offset, line) for each line of the entry
+        let mut lines = s
+            .split_inclusive('\n')
+            .scan(0, |pos, l| {
+                *pos += l.len();
+                Some((*pos - l.len(), l))
+            })
+            .enumerate()
+            .map(|(i, (pos, l))| (i + 1, pos, l.trim()))
+            .skip_while(|(_, _, l)| l.is_empty());
+
+        let (line, timestamp) = match lines.next() {
+            Some((i, _, l)) => (i, l.strip_prefix("### ")),
+            None => (1, None),
+        };
+        let header = timestamp.ok_or(EntryParseError::MissingHeader { line })?;
+        // The ID follows the timestamp as `{#id}`, entries written before IDs
+        // existed are given one from their contents
+        let (timestamp, id) = match header.rsplit_once(" {#") {
+            Some((ts, id)) if id.ends_with('}') => (ts, Some(&id[..id.len() - 1])),
+            _ => (header, None),
+        };
+        let timestamp = Entry::parse_timestamp(timestamp.trim(), dt_format)
+            .map_err(|source| EntryParseError::BadTimestamp { line, source })?;
+
+        // The sentiment line is optional, notebooks may have it turned off
+        let mut next = lines.next();
+        let mut sentiment = None;
+        if let Some((line, _, l)) = next {
+            if let Some(l) = l.strip_prefix("#### ") {
+                let compound: f64 = l
+                    .split_once('≅')
+                    .and_then(|(c, _)| c.trim().parse().ok())
+                    .ok_or(EntryParseError::BadSentiment { line })?;
+                sentiment = Some(Sentiment::new(compound));
+                next = lines.next();
+            }
+        }
+
+        // Only the first separator counts, the body may contain its own
+        let mut body = match next {
+            Some((_, pos, "---")) => s[pos..].split_once('\n').map_or("", |(_, b)| b),
+            Some((line, _, _)) => return Err(EntryParseError::MissingSeparator { line }),
+            None => {
+                return Err(EntryParseError::MissingSeparator {
+                    line: s.lines().count() + 1,
+                })
+            }
+        };
+
+        // Drop the terminator and anything after it, if present
+        let mut pos = 0;
+        for l in body.split_inclusive('\n') {
+            if is_terminator(l) {
+                body = &body[..pos];
+                break;
+            }
+            pos += l.len();
+        }
+
+        // Bodies are written framed by a blank line either side, anything
+        // else has been edited by hand and is trimmed instead
+        let text = body
+            .strip_prefix('\n')
+            .and_then(|b| b.strip_suffix("\n\n"))
+            .unwrap_or_else(|| body.trim());
+
+        let text = unescape(text);
+        let (tags, people) = Entry::extract_tags(&text);
+        Ok(Entry {
+            id: id.map_or_else(|| Entry::derive_id(&timestamp, &text, 0), String::from),
+            text,
+            timestamp,
+            tags,
+            people,
+            dt_format: dt_format.to_string(),
+            sentiment,
+        })
+    }
+
+    /// Checks that an entry written with `dt_format` is read back with the
+    /// same timestamp, to the minute.
+    pub fn check_dt_format(dt_format: &str) -> Result<(), DtFormatError> {
+        use std::fmt::Write;
+
+        let timestamp = NaiveDate::from_ymd_opt(2021, 5, 13)
+            .and_then(|d| d.and_hms_opt(22, 17, 0))
+            .unwrap();
+        // Formatting fails, rather than panicking, on a bad specifier
+        let mut written = String::new();
+        write!(written, "{}", timestamp.format(dt_format)).map_err(|_| DtFormatError::Invalid)?;
+
+        let entry = Entry {
+            timestamp,
+            ..Entry::new("Dear diary".into(), dt_format, false)
+        };
+        let read = Entry::parse_with_format(&entry.to_string(), dt_format)
+            .map_err(DtFormatError::Unreadable)?
+            .timestamp;
+        if read != timestamp {
+            return Err(DtFormatError::Lossy { written, read });
+        }
+
+        Ok(())
+    }
+
+    fn parse_timestamp(s: &str, dt_format: &str) -> Result<NaiveDateTime, ParseError> {
+        let parse = |f: &str| {
+            NaiveDateTime::parse_from_str(s, f).or_else(|e| {
+                // Formats without a time component resolve to midnight
+                NaiveDate::parse_from_str(s, f)
+                    .map(|d| d.and_hms_opt(0, 0, 0).unwrap())
+                    .map_err(|_| e)
+            })
+        };
+
+        parse(dt_format).or_else(|e| KNOWN_DT_FORMATS.iter().find_map(|f| parse(f).ok()).ok_or(e))
     }
 
     fn calculate_sentiment(text: &str) -> f64 {
@@ -35,65 +372,147 @@ impl Entry {
         let analyzer = SentimentIntensityAnalyzer::new();
         let scores = analyzer.polarity_scores(text);
 
-        *scores.get("compound").unwrap()
+        scores.get("compound").copied().unwrap_or_default()
     }
 }
 
 impl FromStr for Entry {
-    type Err = ParseError;
+    type Err = EntryParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let e: Vec<&str> = s.split("---").collect();
-        // TODO: Date format still hardcoded here
-        // Use str::split_once when available
-        // Or use regex
-        let header: Vec<&str> = e[0].trim().split('\n').collect();
-        let compound: f64 = header[1].split('≅').collect::<Vec<&str>>()[0][5..]
-            .trim()
-            .parse()
-            .unwrap();
-        Ok(Entry {
-            text: e[1].trim().into(),
-            timestamp: NaiveDateTime::parse_from_str(
-                header[0].split_at(4).1,
-                "%A %e %B, %Y - %H:%M",
-            )
-            .unwrap(),
-            dt_format: "%A %e %B, %Y - %H:%M".to_string(),
-            sentiment: { Sentiment::new(compound) },
-        })
+        Entry::parse_with_format(s, DEFAULT_DT_FORMAT)
     }
 }
 
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
+        writeln!(
             f,
-            "### {}\n#### {}\n---\n\n{}\n\n¶\n",
+            "### {} {{#{}}}",
             self.timestamp.format(&self.dt_format),
-            self.sentiment,
-            self.text
-        )
+            self.id
+        )?;
+        if let Some(s) = &self.sentiment {
+            writeln!(f, "#### {s}")?;
+        }
+        write!(f, "---\n\n{}\n\n{TERMINATOR}\n", escape(&self.text))
     }
 }
 
+/// Whether a line marks the end of an entry.
+pub fn is_terminator(line: &str) -> bool {
+    line.trim_end_matches(['\n', '\r']) == TERMINATOR
+}
+
+/// Whether a body line reads as a terminator once leading backslashes are removed.
+fn needs_escape(line: &str) -> bool {
+    is_terminator(line.trim_start_matches('\\'))
+}
+
+/// Body lines that look like a terminator get an extra leading backslash,
+/// so `¶` is written as `\¶`, `\¶` as `\\¶` and so on.
+fn escape(text: &str) -> String {
+    text.split('\n')
+        .map(|l| {
+            if needs_escape(l) {
+                format!("\\{l}")
+            } else {
+                l.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn unescape(text: &str) -> String {
+    text.split('\n')
+        .map(|l| match l.strip_prefix('\\') {
+            Some(u) if needs_escape(l) => u,
+            _ => l,
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
 #[cfg(test)]
 mod test_entry {
     use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_roundtrip_arbitrary_text(text in any::<String>()) {
+            let e = Entry::new(text, DEFAULT_DT_FORMAT, true);
+            let s = e.to_string();
+            let parsed = Entry::from_str(&s).unwrap();
+            prop_assert_eq!(&parsed.text, &e.text);
+            prop_assert_eq!(parsed.to_string(), s);
+        }
+
+        #[test]
+        fn test_roundtrip_markup_lines(
+            lines in prop::collection::vec(
+                prop_oneof![
+                    Just("---".to_string()),
+                    Just("¶".to_string()),
+                    Just("\\¶".to_string()),
+                    Just("\\\\¶\r".to_string()),
+                    Just("### Heading".to_string()),
+                    Just(String::new()),
+                    "\\PC*",
+                ],
+                0..10,
+            )
+        ) {
+            let e = Entry::new(lines.join("\n"), DEFAULT_DT_FORMAT, true);
+            let s = e.to_string();
+            let parsed = Entry::from_str(&s).unwrap();
+            prop_assert_eq!(&parsed.text, &e.text);
+            prop_assert_eq!(parsed.to_string(), s);
+        }
+    }
 
     #[test]
     fn test_entry_text() {
-        let e = Entry::new("Testing this entry".into(), "%A %e %B, %Y - %H:%M");
+        let e = Entry::new("Testing this entry".into(), "%A %e %B, %Y - %H:%M", true);
         assert_eq!(e.text, "Testing this entry");
     }
 
+    #[test]
+    fn test_entry_tags() {
+        let e = Entry::new(
+            "#work lunch with @Carrie and @Lupin, #Work again #1 not a tag\n#### or a heading"
+                .into(),
+            DEFAULT_DT_FORMAT,
+            false,
+        );
+        assert_eq!(e.tags, ["work"]);
+        assert_eq!(e.people, ["Carrie", "Lupin"]);
+        assert!(e.has_tag("WORK"));
+        assert!(e.has_tag("#work"));
+        assert!(e.has_tag("@carrie"));
+        assert!(!e.has_tag("Carrie"));
+
+        let parsed = Entry::from_str(&e.to_string()).unwrap();
+        assert_eq!(parsed.tags, e.tags);
+        assert_eq!(parsed.people, e.people);
+    }
+
+    #[test]
+    fn test_replace_text_retags() {
+        let mut e = Entry::new("#old".into(), DEFAULT_DT_FORMAT, false);
+        e.replace_text("#new");
+        assert_eq!(e.tags, ["new"]);
+    }
+
     #[test]
     fn test_sentiment_positive() {
         let e = Entry::new(
             "This is amazing and should have a really high and awesome compound sentiment!".into(),
             "%A %e %B, %Y - %H:%M",
+            true,
         );
-        assert_eq!(e.sentiment.compound, 0.86732358124633);
+        assert_eq!(e.sentiment.unwrap().compound, 0.86732358124633);
     }
 
     #[test]
@@ -101,8 +520,20 @@ mod test_entry {
         let e = Entry::new(
             "This is awful and should have a really low and terrible compound sentiment!".into(),
             "%A %e %B, %Y - %H:%M",
+            true,
         );
-        assert_eq!(e.sentiment.compound, -0.8157728811846393);
+        assert_eq!(e.sentiment.unwrap().compound, -0.8157728811846393);
+    }
+
+    #[test]
+    fn test_sentiment_disabled() {
+        let e = Entry::new("This is amazing!".into(), DEFAULT_DT_FORMAT, false);
+        assert!(e.sentiment.is_none());
+        assert!(!e.to_string().contains("####"));
+
+        let parsed = Entry::from_str(&e.to_string()).unwrap();
+        assert!(parsed.sentiment.is_none());
+        assert_eq!(parsed.text, "This is amazing!");
     }
 
     #[test]
@@ -110,8 +541,151 @@ mod test_entry {
         let mut e = Entry::new(
             "Going to replace some words in this.".into(),
             "%A %e %B, %Y - %H:%M",
+            true,
         );
         e.replace_text("Replaced some words.");
         assert_eq!(e.text, "Replaced some words.");
     }
+
+    #[test]
+    fn test_replace_text_rescores() {
+        let mut e = Entry::new(
+            "This is awful and terrible.".into(),
+            "%A %e %B, %Y - %H:%M",
+            true,
+        );
+        e.replace_text("This is amazing and awesome!");
+        assert!(e.sentiment.unwrap().compound > 0.7);
+
+        let mut e = Entry::new("This is awful.".into(), "%A %e %B, %Y - %H:%M", false);
+        e.replace_text("This is amazing!");
+        assert!(e.sentiment.is_none());
+    }
+
+    #[test]
+    fn test_rescore() {
+        let mut e = Entry::from_str(
+            "### Thursday 13 May, 2021 - 22:17\n#### 0.000 ≅ 🐱\n---\n\nThis is amazing!\n\n",
+        )
+        .unwrap();
+        e.rescore(true);
+        assert!(e.to_string().contains("#### 0.624 ≅ 😺"));
+        e.rescore(false);
+        assert!(e.sentiment.is_none());
+    }
+
+    #[test]
+    fn test_parse_with_format() {
+        let e = Entry::new("Custom format.".into(), "%Y-%m-%d %H:%M", true);
+        let parsed = Entry::parse_with_format(&e.to_string(), "%Y-%m-%d %H:%M").unwrap();
+        assert_eq!(parsed.text, "Custom format.");
+        assert_eq!(
+            parsed.timestamp.format("%Y-%m-%d %H:%M").to_string(),
+            e.timestamp.format("%Y-%m-%d %H:%M").to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_falls_back_to_known_formats() {
+        let s = "### Thursday 13 May, 2021 - 22:17\n#### 0.000 ≅ 🐱\n---\n\nText\n\n";
+        let e = Entry::parse_with_format(s, "%d.%m.%Y %H:%M").unwrap();
+        assert_eq!(e.timestamp.to_string(), "2021-05-13 22:17:00");
+        assert!(e.to_string().starts_with("### 13.05.2021 22:17 {#"));
+    }
+
+    #[test]
+    fn test_parse_date_only_format() {
+        let s = "### 2021-05-13\n#### 0.000 ≅ 🐱\n---\n\nText\n\n";
+        let e = Entry::parse_with_format(s, "%Y-%m-%d").unwrap();
+        assert_eq!(e.timestamp.to_string(), "2021-05-13 00:00:00");
+    }
+
+    #[test]
+    fn test_parse_unknown_format() {
+        let s = "### 13th of May\n#### 0.000 ≅ 🐱\n---\n\nText\n\n";
+        let err = Entry::parse_with_format(s, "%d.%m.%Y %H:%M").unwrap_err();
+        assert!(matches!(err, EntryParseError::BadTimestamp { line: 1, .. }));
+    }
+
+    #[test]
+    fn test_parse_missing_header() {
+        let err = Entry::from_str("\nJust some text\n").unwrap_err();
+        assert_eq!(err, EntryParseError::MissingHeader { line: 2 });
+    }
+
+    #[test]
+    fn test_parse_bad_sentiment() {
+        let s = "### Thursday 13 May, 2021 - 22:17\n#### happy\n---\n\nText\n\n";
+        let err = Entry::from_str(s).unwrap_err();
+        assert_eq!(err, EntryParseError::BadSentiment { line: 2 });
+        assert_eq!(err.to_string(), "could not parse sentiment line");
+    }
+
+    #[test]
+    fn test_parse_missing_separator() {
+        let s = "### Thursday 13 May, 2021 - 22:17\n#### 0.000 ≅ 🐱\n\nText\n\n";
+        let err = Entry::from_str(s).unwrap_err();
+        assert_eq!(err, EntryParseError::MissingSeparator { line: 3 });
+        assert_eq!(err.offset(40).line(), 43);
+    }
+
+    #[test]
+    fn test_parse_id() {
+        let s = "### Thursday 13 May, 2021 - 22:17 {#c0ffee}\n---\n\nText\n\n";
+        let e = Entry::from_str(s).unwrap();
+        assert_eq!(e.id, "c0ffee");
+        assert_eq!(e.timestamp.to_string(), "2021-05-13 22:17:00");
+        assert_eq!(e.to_string(), format!("{s}¶\n"));
+    }
+
+    #[test]
+    fn test_legacy_entry_gets_stable_id() {
+        let s = "### Thursday 13 May, 2021 - 22:17\n---\n\nText\n\n";
+        let a = Entry::from_str(s).unwrap();
+        let b = Entry::from_str(s).unwrap();
+        assert_eq!(a.id.len(), ID_LEN);
+        assert_eq!(a.id, b.id);
+
+        let mut e = Entry::from_str(&a.to_string()).unwrap();
+        assert_eq!(e.id, a.id);
+        e.replace_text("Other text");
+        assert_eq!(e.id, a.id);
+    }
+
+    #[test]
+    fn test_reassign_id() {
+        let mut e = Entry::new("Text".into(), DEFAULT_DT_FORMAT, false);
+        let first = e.id.clone();
+        e.reassign_id(|id| id == first);
+        assert_ne!(e.id, first);
+        assert_eq!(e.id.len(), ID_LEN);
+    }
+
+    #[test]
+    fn test_entry_ref() {
+        assert_eq!("3".parse(), Ok(EntryRef::Index(3)));
+        assert_eq!("-1".parse(), Ok(EntryRef::FromEnd(1)));
+        assert_eq!("C0FFEE".parse(), Ok(EntryRef::Id("c0ffee".into())));
+        assert_eq!("123456".parse(), Ok(EntryRef::Id("123456".into())));
+        assert!("-0".parse::<EntryRef>().is_err());
+        assert!("a b".parse::<EntryRef>().is_err());
+        assert_eq!(EntryRef::FromEnd(2).to_string(), "-2");
+    }
+
+    #[test]
+    fn test_check_dt_format() {
+        assert_eq!(Entry::check_dt_format(DEFAULT_DT_FORMAT), Ok(()));
+        assert_eq!(Entry::check_dt_format("%d.%m.%Y %H:%M"), Ok(()));
+        assert_eq!(Entry::check_dt_format("%Q"), Err(DtFormatError::Invalid));
+        assert!(matches!(
+            Entry::check_dt_format("%H:%M"),
+            Err(DtFormatError::Unreadable(
+                EntryParseError::BadTimestamp { .. }
+            ))
+        ));
+        assert_eq!(
+            Entry::check_dt_format("%Y-%m-%d").unwrap_err().to_string(),
+            "writes '2021-05-13', which reads back as 2021-05-13 00:00:00"
+        );
+    }
 }
@@ -0,0 +1,170 @@
+use crate::{
+    crypto::CryptoError, date::DateParseError, entry::DtFormatError, entry::EntryParseError,
+    entry::EntryRef, lock::LockError, EditorError,
+};
+use std::{error, fmt, io, path::PathBuf};
+
+/// Everything that can go wrong using a notebook, returned from every
+/// fallible public function in the crate.
+#[derive(Debug)]
+pub enum Error {
+    /// The config file couldn't be read or written.
+    Config(confy::ConfyError),
+    /// No notebook has this name, `known` being the ones the config does have.
+    UnknownNotebook {
+        name: String,
+        known: Vec<String>,
+    },
+    NotebookExists(String),
+    /// The notebook used without `-j` can't be removed.
+    RemoveDefault(String),
+    /// A notebook's `file` uses a variable that isn't set.
+    UnsetVariable {
+        path: String,
+        var: String,
+    },
+    /// A `dt_format` that entry headers can't be written with.
+    DtFormat {
+        dt_format: String,
+        source: DtFormatError,
+    },
+    Io {
+        context: String,
+        source: io::Error,
+    },
+    /// An entry in the notebook file that couldn't be read.
+    Parse {
+        path: PathBuf,
+        source: EntryParseError,
+    },
+    Crypto {
+        path: PathBuf,
+        source: CryptoError,
+    },
+    Editor(EditorError),
+    Lock(LockError),
+    NotFound {
+        entry: EntryRef,
+        path: PathBuf,
+    },
+    Date(DateParseError),
+    Pattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    /// Command line arguments that don't make up a command.
+    Usage(String),
+    /// `nb config check` found this many problems.
+    CheckFailed(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(e) => write!(f, "unable to load config: {e}"),
+            Error::UnknownNotebook { name, known } if known.is_empty() => write!(
+                f,
+                "no notebook named '{name}', add one with `nb notebook add {name} <path>`"
+            ),
+            Error::UnknownNotebook { name, known } => write!(
+                f,
+                "no notebook named '{name}', configured notebooks are: {}",
+                known.join(", ")
+            ),
+            Error::NotebookExists(name) => write!(f, "a notebook named '{name}' already exists"),
+            Error::RemoveDefault(name) => write!(
+                f,
+                "'{name}' is the default notebook, set `notebook` in [defaults] to another before removing it"
+            ),
+            Error::UnsetVariable { path, var } => {
+                write!(f, "notebook file '{path}' uses ${var}, which isn't set")
+            }
+            Error::DtFormat { dt_format, source } => write!(f, "dt_format '{dt_format}' {source}"),
+            Error::Io { context, source } => write!(f, "{context}: {source}"),
+            Error::Parse { path, source } => {
+                write!(f, "{}:{}: {source}", path.display(), source.line())
+            }
+            Error::Crypto { path, source } => write!(f, "{}: {source}", path.display()),
+            Error::Editor(e) => write!(f, "{e}"),
+            Error::Lock(e) => write!(f, "{e}"),
+            Error::NotFound { entry, path } => {
+                write!(f, "no entry '{entry}' in '{}'", path.display())
+            }
+            Error::Date(e) => write!(f, "{e}"),
+            // Regex errors span several lines, so only the pattern is shown
+            Error::Pattern { pattern, .. } => write!(f, "invalid search pattern '{pattern}'"),
+            Error::Usage(msg) => write!(f, "{msg}"),
+            Error::CheckFailed(1) => write!(f, "config check found 1 problem"),
+            Error::CheckFailed(n) => write!(f, "config check found {n} problems"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Config(e) => Some(e),
+            Error::UnknownNotebook { .. }
+            | Error::NotebookExists(_)
+            | Error::RemoveDefault(_)
+            | Error::UnsetVariable { .. } => None,
+            Error::DtFormat { source, .. } => Some(source),
+            Error::Io { source, .. } => Some(source),
+            Error::Parse { source, .. } => Some(source),
+            Error::Crypto { source, .. } => Some(source),
+            Error::Editor(e) => Some(e),
+            Error::Lock(e) => Some(e),
+            Error::NotFound { .. } => None,
+            Error::Date(e) => Some(e),
+            Error::Pattern { source, .. } => Some(source),
+            Error::Usage(_) | Error::CheckFailed(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io {
+            context: "i/o error".into(),
+            source,
+        }
+    }
+}
+
+impl From<confy::ConfyError> for Error {
+    fn from(e: confy::ConfyError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<EditorError> for Error {
+    fn from(e: EditorError) -> Self {
+        Error::Editor(e)
+    }
+}
+
+impl From<LockError> for Error {
+    fn from(e: LockError) -> Self {
+        Error::Lock(e)
+    }
+}
+
+impl From<DateParseError> for Error {
+    fn from(e: DateParseError) -> Self {
+        Error::Date(e)
+    }
+}
+
+/// Adds a description of what was being attempted to an I/O error.
+pub(crate) trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T, Error>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context(self, context: impl Into<String>) -> Result<T, Error> {
+        self.map_err(|source| Error::Io {
+            context: context.into(),
+            source,
+        })
+    }
+}
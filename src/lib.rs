@@ -1,25 +1,62 @@
 use crate::notebook::Notebook;
 
-use entry::Entry;
-use serde::{Deserialize, Serialize};
-use std::{env, fmt, fs, io, io::prelude::*, process::Command};
+use chrono::NaiveDateTime;
+use date::{DateRange, DateWindow};
+use entry::{Entry, EntryRef};
+use error::Context;
+pub use error::Error;
+use std::{
+    env, fmt, fs, io,
+    io::prelude::*,
+    path::Path,
+    process::{Command, ExitStatus},
+};
+use tempfile::{TempDir, TempPath};
 
 pub mod argparse;
 pub mod config;
+pub mod crypto;
+pub mod date;
 pub mod entry;
+pub mod error;
+pub mod lock;
 pub mod notebook;
+pub mod storage;
 
 #[derive(Clone, Debug)]
 pub enum Args {
-    New(Entry),
-    List(usize, u8),
-    Read(usize),
-    Edit(usize),
-    Delete(usize, bool),
-    Search(String),
-    DateFilter(String),
-    DateSearch(String),
-    Unimplemented(),
+    /// The new entry's text, or `None` to write it in the editor.
+    New(Option<String>),
+    List(usize, u8, Option<String>),
+    Read(EntryRef),
+    Edit(EntryRef),
+    Delete(EntryRef, bool),
+    Undelete(usize),
+    Search(String, Option<String>),
+    DateFilter(String, DateRange, Option<String>),
+    DateSearch(NaiveDateTime, DateWindow, u8),
+    Rescore,
+    Tags,
+}
+
+/// Commands that manage the config file rather than a notebook.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigArgs {
+    AddNotebook(String, String),
+    ListNotebooks,
+    RemoveNotebook(String),
+    RenameNotebook(String, String),
+    Check,
+}
+
+impl Args {
+    /// Whether the command changes the notebook, and so needs it locked.
+    pub fn modifies(&self) -> bool {
+        matches!(
+            self,
+            Args::New(_) | Args::Edit(_) | Args::Delete(..) | Args::Undelete(_) | Args::Rescore
+        )
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -52,55 +89,200 @@ impl fmt::Display for Sentiment {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct EncryptionScheme {
-    cipher: bool,
-    hash: bool,
-    salt: bool,
-}
-
-fn get_user_confirm<R>(mut reader: R, prompt: String) -> bool
+/// Asks a yes or no question, where anything but `y` or `yes` means no.
+fn get_user_confirm<R, W>(mut reader: R, mut writer: W, prompt: &str) -> io::Result<bool>
 where
     R: io::BufRead,
+    W: io::Write,
 {
-    print!("{prompt} (Y/n) ");
-    let _ = io::stdout().flush();
+    write!(writer, "{prompt} (y/N) ")?;
+    writer.flush()?;
     let mut buffer = String::new();
 
-    reader.read_line(&mut buffer).expect("Error reading input.");
+    reader.read_line(&mut buffer)?;
+
+    let answer = buffer.trim();
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
+
+/// Creates a uniquely named `.md` file in the temp dir, readable only by the
+/// current user, which is removed when the returned path is dropped.
+pub fn create_temp_file(prefix: Option<&str>) -> Result<TempPath, Error> {
+    Ok(tempfile::Builder::new()
+        .prefix(prefix.unwrap_or("notebook_rs"))
+        .suffix(".md")
+        .tempfile()
+        .context("unable to create temp file")?
+        .into_temp_path())
+}
+
+/// Replaces the file at `path` without ever leaving it half written.
+///
+/// The contents go to a temp file in the same directory, which is synced and
+/// renamed over the original, keeping the original's permissions. A symlink
+/// is followed, so it is the file it points to that gets replaced.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    replace_file(path, contents).context(format!("unable to write to '{}'", path.display()))
+}
+
+fn replace_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+
+    let mut temp = tempfile::Builder::new()
+        .prefix(".notebook_rs")
+        .suffix(".tmp")
+        .tempfile_in(dir)?;
+    temp.write_all(contents)?;
+    if let Ok(meta) = fs::metadata(&path) {
+        temp.as_file().set_permissions(meta.permissions())?;
+    }
+    temp.as_file().sync_all()?;
+    temp.persist(&path).map_err(|e| e.error)?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+/// Why no text came back from the editor.
+#[derive(Debug)]
+pub enum EditorError {
+    NotSet,
+    Io(io::Error),
+    Failed(ExitStatus),
+    Empty,
+    Unchanged,
+}
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditorError::NotSet => write!(
+                f,
+                "no editor found, set $VISUAL or $EDITOR, or `editor` in the config"
+            ),
+            EditorError::Io(e) => write!(f, "unable to run editor: {e}"),
+            EditorError::Failed(status) => write!(f, "editor {status}, nothing saved"),
+            EditorError::Empty => write!(f, "entry is empty, nothing saved"),
+            EditorError::Unchanged => write!(f, "entry is unchanged, nothing saved"),
+        }
+    }
+}
+
+impl std::error::Error for EditorError {}
 
-    buffer.starts_with('Y')
+/// Opens the user's editor on a temp file holding `initial`, returning what
+/// was saved. `$VISUAL` is preferred, then `$EDITOR`, then `configured`.
+///
+/// Given `private`, the file is written to a directory there that only the
+/// current user can open, rather than to the shared temp dir, and removed
+/// along with it however the editor exits.
+pub fn text_from_editor(
+    initial: Option<&str>,
+    configured: Option<&str>,
+    private: Option<&Path>,
+) -> Result<String, Error> {
+    let editor = find_editor(configured).ok_or(Error::Editor(EditorError::NotSet))?;
+
+    run_editor(&editor, initial, private)
 }
 
-pub fn create_temp_file(filename: Option<&str>) -> String {
-    let mut file_path = env::temp_dir();
-    file_path.push(filename.unwrap_or("notebook_rs"));
-    fs::File::create(&file_path).expect("Could not create file.");
-    file_path.into_os_string().into_string().unwrap()
+/// The editor command to run, split into words, if one is set anywhere.
+fn find_editor(configured: Option<&str>) -> Option<Vec<String>> {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|v| env::var(v).ok())
+        .chain(configured.map(String::from))
+        .map(|e| split_command(&e))
+        .find(|e| !e.is_empty())
 }
 
-pub fn text_from_editor(path: Option<String>) -> Option<String> {
-    let editor = env::var("EDITOR").expect("EDITOR environment variable is missing.");
-    let file_path = path.unwrap_or_else(|| create_temp_file(None));
+fn run_editor(
+    editor: &[String],
+    initial: Option<&str>,
+    private: Option<&Path>,
+) -> Result<String, Error> {
+    // Declared first, so it outlives the file inside it
+    let private_dir = private.map(create_private_dir).transpose()?;
+    let file_path = match &private_dir {
+        Some(dir) => tempfile::Builder::new()
+            .prefix("notebook_rs")
+            .suffix(".md")
+            .tempfile_in(dir.path())
+            .context("unable to create temp file")?
+            .into_temp_path(),
+        None => create_temp_file(None)?,
+    };
+    let temp_context = || format!("unable to use temp file '{}'", file_path.display());
+    if let Some(text) = initial {
+        fs::write(&file_path, text).context(temp_context())?;
+    }
 
-    Command::new(editor)
+    let status = Command::new(&editor[0])
+        .args(&editor[1..])
         .arg(&file_path)
         .status()
-        .expect("Something went wrong with the editor.");
-
-    let mut text = String::new();
-    fs::File::open(&file_path)
-        .expect("Couldn't open temp file.")
-        .read_to_string(&mut text)
-        .expect("Couldn't load file to string.");
+        .map_err(EditorError::Io)?;
+    if !status.success() {
+        return Err(EditorError::Failed(status).into());
+    }
 
-    fs::remove_file(file_path).expect("Couldn't remove temp file.");
+    let text = fs::read_to_string(&file_path).context(temp_context())?;
+    file_path.close().context("unable to remove temp file")?;
 
-    if text.is_empty() {
-        None
+    if text.trim().is_empty() {
+        Err(EditorError::Empty.into())
+    } else if initial.is_some_and(|i| i.trim() == text.trim()) {
+        Err(EditorError::Unchanged.into())
     } else {
-        Some(text)
+        Ok(text)
+    }
+}
+
+/// Creates a hidden directory in `dir` that only the current user can open,
+/// which is removed with its contents when dropped.
+fn create_private_dir(dir: &Path) -> Result<TempDir, Error> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".notebook_rs");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o700));
+    }
+    builder.tempdir_in(dir).context(format!(
+        "unable to create a directory in '{}'",
+        dir.display()
+    ))
+}
+
+/// Splits an editor command such as `code --wait` into its words, keeping
+/// quoted words together.
+fn split_command(cmd: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+
+    for c in cmd.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
     }
+    words.extend(word);
+
+    words
 }
 
 #[cfg(test)]
@@ -109,20 +291,146 @@ mod test_util {
 
     #[test]
     fn test_missing_editor_variable() {
+        env::remove_var("VISUAL");
         env::remove_var("EDITOR");
-        let result = std::panic::catch_unwind(|| text_from_editor(None));
-        assert!(result.is_err());
+        let result = text_from_editor(None, None, None);
+        assert!(matches!(result, Err(Error::Editor(EditorError::NotSet))));
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("vim"), ["vim"]);
+        assert_eq!(split_command("  code --wait "), ["code", "--wait"]);
+        assert_eq!(
+            split_command("'/opt/my editor/bin' -c \"set ft=md\""),
+            ["/opt/my editor/bin", "-c", "set ft=md"]
+        );
+        assert!(split_command(" ").is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_editor_outcomes() {
+        let cmd = |c: &[&str]| c.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let write = cmd(&["sh", "-c", "printf 'Dear diary' > \"$0\""]);
+
+        assert_eq!(run_editor(&write, None, None).unwrap(), "Dear diary");
+        assert_eq!(
+            run_editor(&write, Some("Old text"), None).unwrap(),
+            "Dear diary"
+        );
+        assert!(matches!(
+            run_editor(&cmd(&["true"]), None, None),
+            Err(Error::Editor(EditorError::Empty))
+        ));
+        assert!(matches!(
+            run_editor(&cmd(&["true"]), Some("Old text"), None),
+            Err(Error::Editor(EditorError::Unchanged))
+        ));
+        assert!(matches!(
+            run_editor(&write, Some("Dear diary\n"), None),
+            Err(Error::Editor(EditorError::Unchanged))
+        ));
+        assert!(matches!(
+            run_editor(&cmd(&["false"]), Some("Old text"), None),
+            Err(Error::Editor(EditorError::Failed(_)))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_editor_in_private_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        // Writes out the path it was given, and the mode of its directory
+        let editor = [
+            "sh",
+            "-c",
+            "printf '%s %s' \"$0\" $(stat -c %a \"$(dirname \"$0\")\") > \"$0\"",
+        ]
+        .map(String::from);
+
+        let text = run_editor(&editor, None, Some(dir.path())).unwrap();
+        let (path, mode) = text.split_once(' ').unwrap();
+        assert!(Path::new(path).starts_with(dir.path()));
+        assert_eq!(mode, "700");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let fail = ["false".to_string()];
+        assert!(run_editor(&fail, Some("Secret."), Some(dir.path())).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notebook.md");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_through_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("notebook.md");
+        let link = dir.path().join("link.md");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_atomic(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_create_temp_file_unique() {
+        let a = create_temp_file(None).unwrap();
+        let b = create_temp_file(None).unwrap();
+        assert_ne!(a.to_path_buf(), b.to_path_buf());
+        assert_eq!(a.extension().unwrap(), "md");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&a).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let path = a.to_path_buf();
+        drop(a);
+        assert!(!path.exists());
     }
 
     #[test]
     fn test_user_confirm_pos() {
-        let pos = b"Y";
-        assert!(get_user_confirm(&pos[..], "Prompt".to_string()))
+        for answer in ["Y", "y\n", "yes\n", " YES \r\n"] {
+            assert!(get_user_confirm(answer.as_bytes(), vec![], "Prompt").unwrap());
+        }
     }
 
     #[test]
     fn test_user_confirm_neg() {
-        let pos = b"n";
-        assert_eq!(get_user_confirm(&pos[..], "Prompt".to_string()), false)
+        for answer in ["n", "\n", "", "no\n", "yeah\n", "Yes please\n"] {
+            assert!(!get_user_confirm(answer.as_bytes(), vec![], "Prompt").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_user_confirm_prompt() {
+        let mut out = vec![];
+        get_user_confirm(&b"y"[..], &mut out, "Delete entry?").unwrap();
+        assert_eq!(out, b"Delete entry? (y/N) ");
     }
 }
@@ -0,0 +1,223 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    io::prelude::*,
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, SystemTime},
+};
+
+/// How long to wait for another `nb` to finish before giving up.
+const WAIT: Duration = Duration::from_secs(2);
+const POLL: Duration = Duration::from_millis(100);
+/// Locks older than this are assumed to have been left behind by a crash,
+/// where there is no telling whether their owner is still running.
+const STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug)]
+pub enum LockError {
+    Locked { pid: u32, path: PathBuf },
+    Io(io::Error),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockError::Locked { pid, path } => write!(
+                f,
+                "notebook is locked by pid {pid}, remove '{}' if it is no longer running",
+                path.display()
+            ),
+            LockError::Io(e) => write!(f, "unable to lock notebook: {e}"),
+        }
+    }
+}
+
+impl Error for LockError {}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> Self {
+        LockError::Io(e)
+    }
+}
+
+/// An exclusive lock on a notebook, held by a `<notebook>.lock` file that
+/// records the owner's pid. The lock is released when this is dropped.
+#[derive(Debug)]
+pub struct NotebookLock {
+    path: PathBuf,
+}
+
+impl NotebookLock {
+    pub fn acquire(notebook: &Path) -> Result<NotebookLock, LockError> {
+        NotebookLock::acquire_within(notebook, WAIT)
+    }
+
+    fn acquire_within(notebook: &Path, wait: Duration) -> Result<NotebookLock, LockError> {
+        let mut path = notebook.as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+        let start = SystemTime::now();
+
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    write!(file, "{}", process::id())?;
+                    return Ok(NotebookLock { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+                Err(e) => return Err(e.into()),
+            }
+
+            let pid = read_pid(&path);
+            if is_stale(&path, pid) {
+                break_lock(&path, pid)?;
+                continue;
+            }
+            if start.elapsed().unwrap_or_default() >= wait {
+                return Err(LockError::Locked { pid, path });
+            }
+            thread::sleep(POLL);
+        }
+    }
+}
+
+impl Drop for NotebookLock {
+    fn drop(&mut self) {
+        // Only a lock that is still ours is removed, in case it was broken
+        // and taken by another process meanwhile
+        if read_pid(&self.path) == process::id() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The pid recorded in a lock file, or 0 if there isn't one yet.
+fn read_pid(path: &Path) -> u32 {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|p| p.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn is_stale(path: &Path, pid: u32) -> bool {
+    // Where /proc is available, a lock is stale exactly when its owner has
+    // gone. A lock without a pid yet is still being written.
+    let proc = Path::new("/proc");
+    if pid != 0 && proc.is_dir() {
+        return !proc.join(pid.to_string()).exists();
+    }
+
+    let age = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|m| m.elapsed().ok())
+        .unwrap_or_default();
+    age > STALE_AFTER
+}
+
+/// Removes the stale lock left by `pid`. It is moved aside first, so that if
+/// another waiter has already broken it and taken the lock, that new lock is
+/// put back rather than removed.
+fn break_lock(path: &Path, pid: u32) -> io::Result<()> {
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(".{}", process::id()));
+    let aside = PathBuf::from(aside);
+
+    match fs::rename(path, &aside) {
+        // Already broken by another waiter
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        r => r?,
+    }
+    if read_pid(&aside) != pid {
+        // Fails, leaving the newer lock in place, if yet another was taken
+        let _ = fs::hard_link(&aside, path);
+    }
+    fs::remove_file(&aside)
+}
+
+#[cfg(test)]
+mod test_lock {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let notebook = dir.path().join("notebook.md");
+
+        let lock = NotebookLock::acquire_within(&notebook, Duration::ZERO).unwrap();
+        let lock_file = dir.path().join("notebook.md.lock");
+        assert_eq!(
+            fs::read_to_string(&lock_file).unwrap(),
+            process::id().to_string()
+        );
+
+        match NotebookLock::acquire_within(&notebook, Duration::ZERO) {
+            Err(LockError::Locked { pid, .. }) => assert_eq!(pid, process::id()),
+            other => panic!("expected lock to be held, got {other:?}"),
+        }
+
+        drop(lock);
+        assert!(!lock_file.exists());
+        assert!(NotebookLock::acquire_within(&notebook, Duration::ZERO).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_stale_lock_is_broken() {
+        let dir = tempfile::tempdir().unwrap();
+        let notebook = dir.path().join("notebook.md");
+        // No process can have this pid on Linux
+        fs::write(dir.path().join("notebook.md.lock"), u32::MAX.to_string()).unwrap();
+
+        let _lock = NotebookLock::acquire_within(&notebook, Duration::ZERO).unwrap();
+        // Only the new lock is left, nothing moved aside
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_old_lock_of_live_process_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let notebook = dir.path().join("notebook.md");
+        let lock_file = dir.path().join("notebook.md.lock");
+        let file = fs::File::create(&lock_file).unwrap();
+        write!(&file, "{}", process::id()).unwrap();
+        file.set_modified(SystemTime::now() - 2 * STALE_AFTER)
+            .unwrap();
+
+        assert!(matches!(
+            NotebookLock::acquire_within(&notebook, Duration::ZERO),
+            Err(LockError::Locked { .. })
+        ));
+    }
+
+    #[test]
+    fn test_drop_keeps_another_process_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let notebook = dir.path().join("notebook.md");
+        let lock = NotebookLock::acquire_within(&notebook, Duration::ZERO).unwrap();
+
+        // As if the lock had been broken and taken by pid 1
+        let lock_file = dir.path().join("notebook.md.lock");
+        fs::write(&lock_file, "1").unwrap();
+        drop(lock);
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_break_lock_puts_back_newer_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_file = dir.path().join("notebook.md.lock");
+        // Judged stale while held by 1234, then retaken by 1 before breaking
+        fs::write(&lock_file, "1").unwrap();
+
+        break_lock(&lock_file, 1234).unwrap();
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), "1");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,21 +1,46 @@
-use notebook_rs::{argparse, config};
+use clap::ArgMatches;
+use notebook_rs::{argparse, config, Error};
+use std::{io, process};
 
 fn main() {
-    let matches = argparse::get_args();
-    let j: &String = matches
-        .get_one("notebook_name")
-        .expect("Error getting notebook name.");
-    let c = matches.try_get_one("config").unwrap();
-    let notebook = config::read_config(j, c).expect("Error reading config file.");
-    config::check_create_file(&notebook.file).expect("Error reading notebook file.");
+    if let Err(e) = run(argparse::get_args()) {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+fn run(matches: ArgMatches) -> Result<(), Error> {
+    // The command line takes precedence over the environment
+    let mut overrides = config::Overrides::from_env();
+    if let Some(c) = matches.get_one::<String>("config") {
+        overrides.config = Some(c.clone());
+    }
+    if let Some(j) = matches.get_one::<String>("notebook_name") {
+        overrides.notebook = Some(j.clone());
+    }
+    if let Some(cmd) = argparse::parse_config_args(&matches)? {
+        return config::run_config_command(cmd, overrides.config.as_ref(), io::stdout());
+    }
+
+    let mut notebook = match matches.get_one::<String>("file") {
+        Some(file) => config::open_file(file, &overrides)?,
+        None => config::read_config(&overrides)?,
+    };
+    notebook.recover |= matches.get_flag("recover");
+    notebook.create()?;
+
+    let args = argparse::parse_args(matches, &notebook)?;
+
+    // Held across the whole read-modify-write, and released on any error.
+    // Recovery may rewrite the notebook whatever the command.
+    let _lock = if args.modifies() || notebook.recover {
+        Some(notebook.lock()?)
+    } else {
+        None
+    };
 
-    let args = argparse::parse_args(matches, &notebook.dt_format);
+    // Nothing is saved if the command fails, e.g. an edit is cancelled
+    notebook.populate_notebook()?.run_command(args)?.save()?;
 
-    notebook
-        .populate_notebook()
-        .expect("Error populating notebook")
-        .run_command(args)
-        .expect("Problem running command")
-        .write_all_entries()
-        .expect("Problem writing all entries");
+    Ok(())
 }
@@ -1,20 +1,66 @@
-use crate::{create_temp_file, get_user_confirm, text_from_editor, Args, EncryptionScheme, Entry};
+use crate::{
+    crypto::{self, EncryptionScheme, Key, Passphrase},
+    entry::{is_terminator, DtFormatError, EntryRef, TERMINATOR},
+    error::{Context, Error},
+    get_user_confirm,
+    lock::NotebookLock,
+    storage::{Storage, StorageKind},
+    text_from_editor, write_atomic, Args, DateRange, DateWindow, Entry,
+};
 use ansi_term::{Colour::Red, Style};
-use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::{cmp, error::Error, fs, io, io::prelude::*, str::FromStr};
+use std::{
+    cell::OnceCell,
+    cmp, fs, io,
+    io::{prelude::*, BufRead},
+    path::{Path, PathBuf},
+};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// How many entries `nb -l` shows unless told otherwise.
+pub const DEFAULT_LIST_LENGTH: usize = 5;
+
+/// A notebook and its settings, as built by `config::read_config`.
+#[derive(Clone, Debug)]
 pub struct Notebook {
     pub file: String,
+    pub storage: StorageKind,
     pub dt_format: String,
-    #[serde(skip)]
     entries: Vec<Entry>,
-    sentiment: bool,
-    encryption: Option<EncryptionScheme>,
-    #[serde(skip)]
+    pub sentiment: bool,
+    pub editor: Option<String>,
+    pub list_length: usize,
+    pub colour: bool,
+    pub encryption: Option<EncryptionScheme>,
+    passphrase: Option<Passphrase>,
+    /// Derived once and used for every file written, as deriving is slow.
+    key: OnceCell<Key>,
+    pub recover: bool,
     search_result: Vec<SearchResult>,
+    pending: Pending,
+    appendable: bool,
+    trash: Option<Trash>,
+    /// Blocks that couldn't be parsed in recovery mode, quarantined on save.
+    rejects: Vec<String>,
+}
+
+/// What needs writing back to the notebook file once a command has run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum Pending {
+    #[default]
+    Nothing,
+    /// This many new entries at the end, which can simply be appended.
+    Append(usize),
+    Rewrite,
+}
+
+/// Deleted entries, oldest deletion first, loaded from the trash file once
+/// a command needs them.
+#[derive(Clone, Debug, Default)]
+struct Trash {
+    entries: Vec<Entry>,
+    /// Whether entries have been taken out, rather than only put in.
+    restored: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -44,155 +90,572 @@ impl Notebook {
     pub fn new() -> Notebook {
         Notebook {
             file: String::new(),
+            storage: StorageKind::File,
             dt_format: String::new(),
             entries: vec![],
             sentiment: true,
+            editor: None,
+            list_length: DEFAULT_LIST_LENGTH,
+            colour: true,
             encryption: None,
+            passphrase: None,
+            key: OnceCell::new(),
+            recover: false,
             search_result: vec![],
+            pending: Pending::Nothing,
+            appendable: false,
+            trash: None,
+            rejects: vec![],
         }
     }
 
-    pub fn write_entry(&self, entry: &Entry, path: &String) -> Result<&Self, Box<dyn Error>> {
-        let mut file = fs::OpenOptions::new()
-            .append(true)
-            .open(path)
-            .context(format!("unable to open or create '{}'", self.file))?;
+    /// The backend the notebook's entries are read from and written to.
+    pub fn storage(&self) -> Box<dyn Storage> {
+        self.storage.open(Path::new(&self.file))
+    }
+
+    /// Creates the notebook's file or directory if it doesn't exist yet.
+    pub fn create(&self) -> Result<&Self, Error> {
+        self.storage().create()?;
+        Ok(self)
+    }
 
-        file.write_all(format!("{entry}").as_bytes())
-            .context(format!("unable to write to '{}'", self.file))?;
+    pub fn new_entry(&mut self, entry: Entry) -> Result<&Self, Error> {
+        self.push_entry(entry);
+        self.pending = match self.pending {
+            Pending::Nothing if self.appendable => Pending::Append(1),
+            Pending::Append(n) => Pending::Append(n + 1),
+            _ => Pending::Rewrite,
+        };
 
         Ok(self)
     }
 
-    pub fn new_entry(&mut self, entry: Entry) -> Result<&Self, Box<dyn Error>> {
+    /// Where the editor's file goes for an encrypted notebook, so that the
+    /// entry never reaches the shared temp dir in plain text.
+    fn private_dir(&self) -> Option<PathBuf> {
+        self.encryption
+            .as_ref()
+            .map(|_| match Path::new(&self.file).parent() {
+                Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+                _ => PathBuf::from("."),
+            })
+    }
+
+    /// Adds an entry with `text`, or with what is written in the editor if
+    /// there isn't any.
+    pub fn write_new_entry(&mut self, text: Option<String>) -> Result<&Self, Error> {
+        let text = match text {
+            Some(t) => t,
+            None => text_from_editor(None, self.editor.as_deref(), self.private_dir().as_deref())?,
+        };
+        self.new_entry(Entry::new(text, &self.dt_format, self.sentiment))
+    }
+
+    /// Adds an entry, first giving it another ID if one already has its own.
+    fn push_entry(&mut self, mut entry: Entry) {
+        entry.reassign_id(|id| self.entries.iter().any(|e| e.id == id));
         self.entries.push(entry);
+    }
 
-        Ok(self)
+    /// Finds the position of the entry `r` points at.
+    pub fn find(&self, r: &EntryRef) -> Result<usize, Error> {
+        let idx = match r {
+            EntryRef::Index(n) => Some(*n),
+            EntryRef::FromEnd(n) => self.entries.len().checked_sub(*n),
+            // An all-digit ID that matches nothing may still be an index
+            EntryRef::Id(id) => self
+                .entries
+                .iter()
+                .position(|e| e.id == *id)
+                .or_else(|| id.parse().ok()),
+        };
+
+        idx.filter(|i| *i < self.entries.len())
+            .ok_or_else(|| self.not_found(r.clone()))
     }
 
-    pub fn write_all_entries(&self) -> Result<&Self, Box<dyn Error>> {
-        // Write all entries to tmp file, overwrite notebook, remove temp file.
-        let temp_file_path = create_temp_file(None);
+    fn not_found(&self, entry: EntryRef) -> Error {
+        Error::NotFound {
+            entry,
+            path: PathBuf::from(&self.file),
+        }
+    }
+
+    /// Writes back whatever the commands run so far have changed, appending
+    /// new entries where possible and leaving the file alone if nothing has.
+    pub fn save(&mut self) -> Result<&Self, Error> {
+        self.check_dt_format()?;
+
+        // Deleted entries reach the trash before they leave the notebook, and
+        // restored ones only leave it once they are back in the notebook
+        let trash = self.trash.take();
+        if let Some(t) = trash.as_ref().filter(|t| !t.restored) {
+            self.write_trash(t)?;
+        }
+        // Likewise unparseable blocks are only dropped once they're quarantined
+        let rejects = std::mem::take(&mut self.rejects);
+        if !rejects.is_empty() {
+            self.quarantine(&rejects)?;
+        }
+
+        match self.pending {
+            Pending::Nothing => (),
+            Pending::Append(n) => {
+                let new = &self.entries[self.entries.len() - n..];
+                self.storage().append(new, &|c, p| self.encode(c, p))?;
+            }
+            Pending::Rewrite => {
+                self.write_all_entries()?;
+            }
+        }
+        self.pending = Pending::Nothing;
 
-        assert!(!self.entries.is_empty()); // Guard against file being wiped
-        for e in &self.entries {
-            self.write_entry(e, &temp_file_path)
-                .expect("Error writing to temp file");
+        if let Some(t) = trash.as_ref().filter(|t| t.restored) {
+            self.write_trash(t)?;
         }
-        fs::copy(&temp_file_path, &self.file)
-            .context(format!("unable to copy file to '{}'", &self.file))?;
-        fs::remove_file(&temp_file_path).expect("Couldn't remove temp file.");
+
         Ok(self)
     }
 
-    /// Opens the file
-    /// Reads the contents to a string
+    pub fn write_all_entries(&self) -> Result<&Self, Error> {
+        self.storage()
+            .write_all(&self.entries, &|c, p| self.encode(c, p), &|b, _| {
+                self.decoded(b)
+            })?;
+        Ok(self)
+    }
+
+    /// Reads each of the storage's files
+    /// Decrypts them if need be
     /// Populates the Notebook instance with entries
-    pub fn populate_notebook(mut self) -> Result<Self, Box<dyn Error>> {
-        let file =
-            fs::read_to_string(&self.file).context(format!("unable to open '{}'", self.file))?;
-        for e in file.split_terminator("¶\n") {
-            self.entries.push(
-                Entry::from_str(e).context(format!("could not read line '{}'", e.to_owned()))?,
-            );
+    ///
+    /// In recovery mode, blocks that can't be parsed are moved to the
+    /// rejects file rather than failing the whole notebook.
+    pub fn populate_notebook(self) -> Result<Self, Error> {
+        self.populate_notebook_with(&mut io::stderr())
+    }
+
+    fn populate_notebook_with<W: Write>(mut self, mut stderr: W) -> Result<Self, Error> {
+        self.check_dt_format()?;
+        let storage = self.storage();
+        let mut last = None;
+        for (path, bytes) in storage.read()? {
+            let contents = self.decode(bytes, &path)?;
+            for (line, e) in split_entries(&contents) {
+                if !e.trim().is_empty() {
+                    match Entry::parse_with_format(e, &self.dt_format) {
+                        Ok(entry) => self.push_entry(entry),
+                        Err(err) => {
+                            let err = Error::Parse {
+                                path: path.clone(),
+                                source: err.offset(line),
+                            };
+                            if !self.recover {
+                                return Err(err);
+                            }
+                            writeln!(stderr, "{err}").context("unable to display error")?;
+                            self.rejects.push(e.to_owned());
+                        }
+                    }
+                }
+            }
+            last = Some(contents);
+        }
+        self.appendable = storage.appendable(last.as_deref(), self.encryption.is_some());
+
+        if !self.rejects.is_empty() {
+            writeln!(
+                stderr,
+                "Recovered {} entries, moving {} unparseable blocks to '{}'",
+                self.entries.len(),
+                self.rejects.len(),
+                self.rejects_file().display()
+            )
+            .context("unable to display error")?;
+            self.pending = Pending::Rewrite;
         }
         Ok(self)
     }
 
-    pub fn read_entry<W: Write>(&self, n: &usize, mut stdout: W) -> Result<&Self, Box<dyn Error>> {
-        let i = &self.entries.get(*n);
+    /// Takes the notebook's lock file, to be held from before it is populated
+    /// until after it is saved.
+    pub fn lock(&self) -> Result<NotebookLock, Error> {
+        Ok(NotebookLock::acquire(Path::new(&self.file))?)
+    }
 
-        match i {
-            Some(e) => write!(stdout, "{e}").context("unable to display entry")?,
-            None => writeln!(stdout, "No such entry.")?,
+    /// Refuses a `dt_format` that can't be written at all, which would
+    /// otherwise panic once an entry is shown or saved. One that merely
+    /// doesn't read back is left to `nb config check`.
+    pub fn check_dt_format(&self) -> Result<(), Error> {
+        match Entry::check_dt_format(&self.dt_format) {
+            Err(source @ DtFormatError::Invalid) => Err(Error::DtFormat {
+                dt_format: self.dt_format.clone(),
+                source,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the passphrase for an encrypted notebook, instead of prompting for it.
+    pub fn set_passphrase(&mut self, passphrase: Passphrase) {
+        self.passphrase = Some(passphrase);
+        self.key = OnceCell::new();
+    }
+
+    fn decode(&mut self, bytes: Vec<u8>, path: &Path) -> Result<String, Error> {
+        if !crypto::is_encrypted(&bytes) {
+            return utf8(bytes, path);
+        }
+
+        // An encrypted notebook stays encrypted, even if the config doesn't say so
+        let scheme = self.encryption.get_or_insert_with(Default::default).clone();
+        let passphrase = match self.passphrase.take() {
+            Some(p) => p,
+            None => Passphrase::obtain(false).map_err(|e| crypto_error(path, e))?,
+        };
+        // Files are sealed with the notebook's key, so only the first one read
+        // needs a key derived. Those from before that have their own.
+        let plaintext = match self.key.get().filter(|k| k.matches(&bytes)) {
+            Some(key) => key.decrypt(&bytes),
+            None => Key::for_bytes(&scheme, &passphrase, &bytes).and_then(|key| {
+                let plaintext = key.decrypt(&bytes)?;
+                let _ = self.key.set(key);
+                Ok(plaintext)
+            }),
+        }
+        .map_err(|e| crypto_error(path, e))?;
+        self.passphrase = Some(passphrase);
+
+        utf8(plaintext, path)
+    }
+
+    fn encode(&self, contents: String, path: &Path) -> Result<Vec<u8>, Error> {
+        let Some(scheme) = &self.encryption else {
+            return Ok(contents.into_bytes());
+        };
+
+        let key = match self.key.get() {
+            Some(key) => key,
+            None => {
+                // A notebook being encrypted for the first time needs a new passphrase
+                let passphrase = match &self.passphrase {
+                    Some(p) => p.clone(),
+                    None => Passphrase::obtain(true).map_err(|e| crypto_error(path, e))?,
+                };
+                let key = Key::generate(scheme, &passphrase).map_err(|e| crypto_error(path, e))?;
+                self.key.get_or_init(|| key)
+            }
+        };
+        key.encrypt(contents.as_bytes())
+            .map_err(|e| crypto_error(path, e))
+    }
+
+    /// What was written with `encode`, without deriving a key. Plaintext
+    /// written for an encrypted notebook, the other way round, or a file
+    /// sealed with another key doesn't count, and is written again.
+    fn decoded(&self, bytes: Vec<u8>) -> Option<String> {
+        let plaintext = match (&self.encryption, self.key.get()) {
+            (None, _) if !crypto::is_encrypted(&bytes) => bytes,
+            (Some(_), Some(key)) if key.matches(&bytes) => key.decrypt(&bytes).ok()?,
+            _ => return None,
+        };
+        String::from_utf8(plaintext).ok()
+    }
+
+    /// Path of the file deleted entries are kept in until they are restored.
+    pub fn trash_file(&self) -> PathBuf {
+        PathBuf::from(&self.file).with_extension("trash.md")
+    }
+
+    fn load_trash(&mut self) -> Result<&mut Trash, Error> {
+        if self.trash.is_none() {
+            let path = self.trash_file();
+            let entries = match fs::read(&path) {
+                Ok(bytes) => {
+                    let contents = self.decode(bytes, &path)?;
+                    let mut entries = vec![];
+                    for (line, e) in split_entries(&contents) {
+                        if !e.trim().is_empty() {
+                            let entry =
+                                Entry::parse_with_format(e, &self.dt_format).map_err(|err| {
+                                    Error::Parse {
+                                        path: path.clone(),
+                                        source: err.offset(line),
+                                    }
+                                })?;
+                            entries.push(entry);
+                        }
+                    }
+                    entries
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+                Err(e) => return Err(e).context(format!("unable to open '{}'", path.display())),
+            };
+            self.trash = Some(Trash {
+                entries,
+                restored: false,
+            });
+        }
+
+        Ok(self.trash.get_or_insert_with(Trash::default))
+    }
+
+    fn write_trash(&self, trash: &Trash) -> Result<&Self, Error> {
+        let path = self.trash_file();
+        if trash.entries.is_empty() {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).context(format!("unable to remove '{}'", path.display()))
+                }
+                _ => (),
+            }
+        } else {
+            let contents: String = trash.entries.iter().map(|e| e.to_string()).collect();
+            write_atomic(&path, &self.encode(contents, &path)?)?;
         }
 
         Ok(self)
     }
 
+    /// Path of the file unparseable blocks are moved to in recovery mode.
+    pub fn rejects_file(&self) -> PathBuf {
+        PathBuf::from(&self.file).with_extension("rejects.md")
+    }
+
+    fn quarantine(&mut self, blocks: &[String]) -> Result<&Self, Error> {
+        // Blocks are added verbatim, so nothing is lost when the notebook is
+        // rewritten, and encrypted like the notebook they came from
+        let path = self.rejects_file();
+        let mut contents = match fs::read(&path) {
+            Ok(bytes) => self.decode(bytes, &path)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context(format!("unable to open '{}'", path.display())),
+        };
+        for b in blocks {
+            contents.push_str(&format!("{b}{TERMINATOR}\n"));
+        }
+        write_atomic(&path, &self.encode(contents, &path)?)?;
+
+        Ok(self)
+    }
+
+    pub fn read_entry<W: Write>(&self, n: &usize, mut stdout: W) -> Result<&Self, Error> {
+        let e = self
+            .entries
+            .get(*n)
+            .ok_or_else(|| self.not_found(EntryRef::Index(*n)))?;
+        write!(stdout, "{e}").context("unable to display entry")?;
+
+        Ok(self)
+    }
+
     pub fn list_entries<W: Write>(
         &self,
         n: &usize,
         mut stdout: W,
         verbose: u8,
-    ) -> Result<&Self, Box<dyn Error>> {
-        // Iterates over last n elements of entries
+        tag: Option<&str>,
+    ) -> Result<&Self, Error> {
+        // Iterates over last n elements of entries, with the tag if given
         // Prints timestamp numbered by enumerate
 
-        let i = cmp::min(self.entries.len(), *n);
+        let found: Vec<usize> = (0..self.entries.len())
+            .filter(|i| tag.is_none_or(|t| self.entries[*i].has_tag(t)))
+            .collect();
+        let i = cmp::min(found.len(), *n);
 
-        for e in self.entries.iter().enumerate().skip(self.entries.len() - i) {
-            let substr = &e.1.text[..cmp::min(usize::from(verbose + 1) * 50, e.1.text.len())];
-            writeln!(
-                stdout,
-                "{}: {}\t{}…",
-                Style::new().bold().paint(e.0.to_string()),
-                Style::new().bold().paint(e.1.timestamp.to_string()),
-                substr,
-            )
-            .context("Error parsing something to string.")?;
+        for idx in &found[found.len() - i..] {
+            self.write_summary(*idx, &mut stdout, verbose)?;
         }
 
         Ok(self)
     }
 
-    pub fn edit_entry(&mut self, n: usize) -> Result<&Self, Box<dyn Error>> {
-        let e = &mut self
-            .entries
-            .get_mut(n)
-            .expect("Unable to read entry, may not exist.");
+    pub fn list_tags<W: Write>(&self, mut stdout: W) -> Result<&Self, Error> {
+        // Counts each #tag, then each @person, most used first
+        for (prefix, names) in [
+            (
+                "#",
+                self.entries.iter().map(|e| &e.tags).collect::<Vec<_>>(),
+            ),
+            ("@", self.entries.iter().map(|e| &e.people).collect()),
+        ] {
+            let mut counts: Vec<(String, usize)> = vec![];
+            for name in names.into_iter().flatten() {
+                match counts
+                    .iter_mut()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                {
+                    Some((_, c)) => *c += 1,
+                    None => counts.push((name.to_owned(), 1)),
+                }
+            }
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+            for (name, count) in counts {
+                writeln!(
+                    stdout,
+                    "{}\t{}",
+                    self.style(Style::new().bold())
+                        .paint(format!("{prefix}{name}")),
+                    count
+                )?;
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// `style`, or no style at all if colour is turned off.
+    fn style(&self, style: Style) -> Style {
+        if self.colour {
+            style
+        } else {
+            Style::new()
+        }
+    }
+
+    fn write_summary<W: Write>(
+        &self,
+        idx: usize,
+        mut stdout: W,
+        verbose: u8,
+    ) -> Result<&Self, Error> {
+        // Prints one line per entry: index, ID, timestamp and the start of the text
+        let e = &self.entries[idx];
+        let substr = &e.text[..e.text.floor_char_boundary((usize::from(verbose) + 1) * 50)];
+        writeln!(
+            stdout,
+            "{} {}: {}\t{}…",
+            self.style(Style::new().bold()).paint(idx.to_string()),
+            self.style(Style::new().dimmed()).paint(&e.id),
+            self.style(Style::new().bold())
+                .paint(e.timestamp.to_string()),
+            substr,
+        )
+        .context("unable to display entry")?;
+
+        Ok(self)
+    }
+
+    pub fn edit_entry(&mut self, n: usize) -> Result<&Self, Error> {
+        let private = self.private_dir();
+        let missing = self.not_found(EntryRef::Index(n));
+        let e = self.entries.get_mut(n).ok_or(missing)?;
 
-        let temp_file = create_temp_file(Some("notebook_entry"));
-        fs::write(&temp_file, &e.text).expect("Error writing to temp file");
-        let edited_entry = text_from_editor(Some(temp_file)).unwrap();
+        let edited_entry =
+            text_from_editor(Some(&e.text), self.editor.as_deref(), private.as_deref())?;
 
         e.replace_text(&edited_entry);
+        self.pending = Pending::Rewrite;
 
         Ok(self)
     }
 
-    pub fn delete_entry(&mut self, n: usize, conf_req: bool) -> Result<&Self, Box<dyn Error>> {
-        if conf_req
-            && get_user_confirm(
-                &mut io::stdin().lock(),
-                format!("Confirm delete entry {n}?"),
-            )
-        {
-            self.entries.remove(n);
-            println!("Deleted entry {n}");
-        } else {
-            self.entries.remove(n);
+    pub fn rescore_entries<W: Write>(&mut self, mut stdout: W) -> Result<&Self, Error> {
+        for e in &mut self.entries {
+            e.rescore(self.sentiment);
+        }
+        writeln!(stdout, "Rescored {} entries", self.entries.len())
+            .context("unable to display entries")?;
+        self.pending = Pending::Rewrite;
+
+        Ok(self)
+    }
+
+    /// Moves entry `n` to the trash, first asking for confirmation if `conf_req` is set.
+    pub fn delete_entry(&mut self, n: usize, conf_req: bool) -> Result<&Self, Error> {
+        self.delete_entry_with(n, conf_req.then(|| io::stdin().lock()), &mut io::stdout())
+    }
+
+    fn delete_entry_with<R: BufRead, W: Write>(
+        &mut self,
+        n: usize,
+        confirm: Option<R>,
+        mut stdout: W,
+    ) -> Result<&Self, Error> {
+        let Some(e) = self.entries.get(n) else {
+            return Err(self.not_found(EntryRef::Index(n)));
+        };
+        let id = e.id.clone();
+
+        if let Some(reader) = confirm {
+            self.write_summary(n, &mut stdout, 0)?;
+            let prompt = format!("Delete entry {id}?");
+            if !get_user_confirm(reader, &mut stdout, &prompt)
+                .context("unable to read confirmation")?
+            {
+                writeln!(stdout, "Entry not deleted").context("unable to display entry")?;
+                return Ok(self);
+            }
+        }
+
+        self.load_trash()?;
+        let e = self.entries.remove(n);
+        if let Some(t) = &mut self.trash {
+            t.entries.push(e);
+        }
+        self.pending = Pending::Rewrite;
+        writeln!(stdout, "Deleted entry {id}, `nb undelete` restores it")
+            .context("unable to display entry")?;
+
+        Ok(self)
+    }
+
+    /// Restores the last `n` deleted entries, each to its place by date.
+    pub fn undelete_entries<W: Write>(&mut self, n: usize, mut stdout: W) -> Result<&Self, Error> {
+        let trash = self.load_trash()?;
+        let restored = trash
+            .entries
+            .split_off(trash.entries.len().saturating_sub(n));
+        if restored.is_empty() {
+            writeln!(stdout, "Nothing to restore").context("unable to display entry")?;
+            return Ok(self);
+        }
+        trash.restored = true;
+
+        for mut e in restored {
+            e.reassign_id(|id| self.entries.iter().any(|e| e.id == id));
+            writeln!(stdout, "Restored entry {}", e.id).context("unable to display entry")?;
+            let idx = self.entries.partition_point(|x| x.timestamp <= e.timestamp);
+            self.entries.insert(idx, e);
         }
+        self.pending = Pending::Rewrite;
 
         Ok(self)
     }
 
-    pub fn run_command(mut self, cmd: Args) -> Result<Self, Box<dyn Error>> {
+    pub fn run_command(mut self, cmd: Args) -> Result<Self, Error> {
         match cmd {
-            Args::New(e) => self.new_entry(e),
-            Args::List(ref n, l) => self.list_entries(n, &mut io::stdout(), l),
-            Args::Read(ref n) => self.read_entry(n, &mut io::stdout()),
-            Args::Edit(n) => self.edit_entry(n),
-            Args::Delete(n, conf) => self.delete_entry(n, conf),
-            Args::Search(s) => self
-                .search(s)
-                .unwrap()
+            Args::New(text) => self.write_new_entry(text),
+            Args::List(ref n, l, ref t) => self.list_entries(n, &mut io::stdout(), l, t.as_deref()),
+            Args::Tags => self.list_tags(&mut io::stdout()),
+            Args::Read(ref r) => self.read_entry(&self.find(r)?, &mut io::stdout()),
+            Args::Edit(ref r) => self.edit_entry(self.find(r)?),
+            Args::Delete(ref r, conf) => self.delete_entry(self.find(r)?, conf),
+            Args::Undelete(n) => self.undelete_entries(n, &mut io::stdout()),
+            Args::Search(s, ref t) => self
+                .search(s, t.as_deref())?
                 .output_search_results(&mut io::stdout()),
-            Args::DateFilter(_s) => panic!("Not implemented"),
-            Args::DateSearch(_s) => panic!("Not implemented"),
-            Args::Unimplemented() => panic!("Not implemented"),
-        }
-        .expect("Error matching command");
+            Args::DateFilter(s, r, ref t) => {
+                self.search(s, t.as_deref())?;
+                self.filter_search_results(&r)?
+                    .output_search_results(&mut io::stdout())
+            }
+            Args::DateSearch(d, ref w, l) => self.date_search(&d, w, &mut io::stdout(), l),
+            Args::Rescore => self.rescore_entries(&mut io::stdout()),
+        }?;
 
         Ok(self)
     }
 
-    fn search(&mut self, q: String) -> Result<&Self, Box<dyn Error>> {
+    fn search(&mut self, q: String, tag: Option<&str>) -> Result<&Self, Error> {
         // TODO: Currently case-sensitive, add flag to toggle
-        let regex = Regex::new(&q).expect("Error compiling regex.");
+        let regex = Regex::new(&q).map_err(|source| Error::Pattern { pattern: q, source })?;
 
         for (e_idx, e) in self.entries.iter().enumerate() {
-            if regex.is_match(&e.text) {
+            if regex.is_match(&e.text) && tag.is_none_or(|t| e.has_tag(t)) {
                 let matches = regex
                     .find_iter(&e.text)
                     .map(|digits| digits.as_str().to_owned())
@@ -206,12 +669,48 @@ impl Notebook {
         Ok(self)
     }
 
-    fn _date_search(&mut self, q: String) -> Result<&Self, Box<dyn Error>> {
-        dbg!(&q);
+    fn filter_search_results(&mut self, range: &DateRange) -> Result<&Self, Error> {
+        // Keep only results whose entry falls inside the date range
+        let entries = &self.entries;
+        self.search_result
+            .retain(|r| range.contains(&entries[r.entry_idx].timestamp));
+
         Ok(self)
     }
 
-    fn output_search_results<W: Write>(&self, mut stdout: W) -> Result<&Self, Box<dyn Error>> {
+    fn date_search<W: Write>(
+        &self,
+        date: &NaiveDateTime,
+        window: &DateWindow,
+        mut stdout: W,
+        verbose: u8,
+    ) -> Result<&Self, Error> {
+        // Lists entries near the date in notebook order, as list_entries does
+        let distance = |i: &usize| (self.entries[*i].timestamp - *date).abs();
+        let mut found: Vec<usize> = (0..self.entries.len()).collect();
+
+        match window {
+            DateWindow::Days(d) => found.retain(|i| {
+                (self.entries[*i].timestamp.date() - date.date())
+                    .num_days()
+                    .abs()
+                    <= *d
+            }),
+            DateWindow::Closest(k) => {
+                found.sort_by_key(distance);
+                found.truncate(*k);
+                found.sort();
+            }
+        }
+
+        for idx in found {
+            self.write_summary(idx, &mut stdout, verbose)?;
+        }
+
+        Ok(self)
+    }
+
+    fn output_search_results<W: Write>(&self, mut stdout: W) -> Result<&Self, Error> {
         // Break string into 50 char blocks
         // Iterate over blocks
         // Print only ones with matches
@@ -219,16 +718,16 @@ impl Notebook {
             write!(
                 stdout,
                 "{}: {}\t",
-                Style::new().bold().paint(r.entry_idx.to_string()),
-                Style::new()
-                    .bold()
+                self.style(Style::new().bold())
+                    .paint(r.entry_idx.to_string()),
+                self.style(Style::new().bold())
                     .paint(self.entries[r.entry_idx].timestamp.to_string())
             )?;
             for (idx, c) in r.pattern.split(&self.entries[r.entry_idx].text).enumerate() {
                 write!(stdout, "{c}")?;
 
                 if let Some(c) = r.location.get(idx) {
-                    write!(stdout, "{}", Red.paint(c))?;
+                    write!(stdout, "{}", self.style(Red.normal()).paint(c))?;
                 };
             }
             writeln!(stdout)?;
@@ -237,16 +736,49 @@ impl Notebook {
     }
 }
 
+fn utf8(bytes: Vec<u8>, path: &Path) -> Result<String, Error> {
+    String::from_utf8(bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        .context(format!("unable to read '{}'", path.display()))
+}
+
+fn crypto_error(path: &Path, source: crypto::CryptoError) -> Error {
+    Error::Crypto {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Splits notebook contents on terminator lines, pairing each entry with the
+/// number of lines that precede it.
+fn split_entries(s: &str) -> Vec<(usize, &str)> {
+    let mut entries = vec![];
+    let (mut start, mut start_line, mut pos) = (0, 0, 0);
+
+    for (i, l) in s.split_inclusive('\n').enumerate() {
+        pos += l.len();
+        if is_terminator(l) {
+            entries.push((start_line, &s[start..pos - l.len()]));
+            (start, start_line) = (pos, i + 1);
+        }
+    }
+    if start < s.len() {
+        entries.push((start_line, &s[start..]));
+    }
+
+    entries
+}
+
 #[cfg(test)]
 mod test_notebook {
     use super::*;
+    use crate::storage::DirectoryStorage;
 
     fn create_notebook() -> Notebook {
         let mut nb = Notebook::new();
         nb.file = "data/test.md".into();
         nb.dt_format = "%A %e %B, %Y - %H:%M".into();
-        let nb = nb.populate_notebook().expect("Error reading notebook.");
-        nb
+        nb.populate_notebook().expect("Error reading notebook.")
     }
 
     #[test]
@@ -258,22 +790,381 @@ mod test_notebook {
         assert_eq!(nb.entries.len(), 4);
     }
 
+    #[test]
+    fn test_populate_notebook_other_format() {
+        let mut nb = Notebook::new();
+        nb.file = "data/test.md".into();
+        nb.dt_format = "%Y-%m-%d %H:%M".into();
+        let nb = nb.populate_notebook().expect("Error reading notebook.");
+        assert_eq!(nb.entries.len(), 4);
+        assert_eq!(nb.entries[0].timestamp.to_string(), "2020-11-20 20:16:00");
+    }
+
+    #[test]
+    fn test_populate_notebook_reports_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notebook.md");
+        let good = Entry::new("Fine.".into(), "%A %e %B, %Y - %H:%M", true).to_string();
+        fs::write(
+            &path,
+            format!("{good}### Not a date\n#### 0.0 ≅ 🐱\n---\n\nBad\n\n¶\n"),
+        )
+        .unwrap();
+
+        let mut nb = Notebook::new();
+        nb.file = path.to_str().unwrap().into();
+        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        let err = nb.populate_notebook().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{}:8: could not parse timestamp", path.display())
+        );
+    }
+
+    #[test]
+    fn test_populate_notebook_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notebook.md");
+        let bad = "### Not a date\n#### 0.0 ≅ 🐱\n---\n\nBad\n\n";
+        let good = Entry::new("Fine.".into(), "%A %e %B, %Y - %H:%M", true).to_string();
+        fs::write(&path, format!("{good}{bad}¶\n{good}")).unwrap();
+
+        let mut nb = Notebook::new();
+        nb.file = path.to_str().unwrap().into();
+        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        nb.recover = true;
+        let mut stderr = vec![];
+        let mut nb = nb.populate_notebook_with(&mut stderr).unwrap();
+        assert_eq!(nb.entries.len(), 2);
+        let stderr = String::from_utf8(stderr).unwrap();
+        assert!(stderr.starts_with(&format!("{}:8: could not parse", path.display())));
+        assert!(stderr.contains("Recovered 2 entries, moving 1 unparseable blocks"));
+
+        // Nothing is moved until the notebook is saved
+        let rejects = nb.rejects_file();
+        assert!(!rejects.exists());
+        nb.save().unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("Bad"));
+        assert_eq!(fs::read_to_string(&rejects).unwrap(), format!("{bad}¶\n"));
+    }
+
+    #[test]
+    fn test_populate_notebook_markup_in_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notebook.md");
+        let texts = [
+            "Above the rule\n\n---\n\nBelow the rule",
+            "¶\nA pilcrow ¶\n¶ and \\¶ escaped",
+            "Plain.",
+        ];
+        fs::File::create(&path).unwrap();
+
+        let mut nb = Notebook::new();
+        nb.file = path.to_str().unwrap().into();
+        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        let mut nb = nb.populate_notebook().unwrap();
+        for t in texts {
+            nb.new_entry(Entry::new(t.into(), "%A %e %B, %Y - %H:%M", true))
+                .unwrap();
+        }
+        assert_eq!(nb.pending, Pending::Append(3));
+        nb.save().unwrap();
+
+        let mut nb = Notebook::new();
+        nb.file = path.to_str().unwrap().into();
+        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        let nb = nb.populate_notebook().unwrap();
+        let read: Vec<&str> = nb.entries.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(read, texts);
+    }
+
+    #[test]
+    fn test_encrypted_notebook() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notebook.md");
+        fs::write(&path, "").unwrap();
+
+        let mut nb = Notebook::new();
+        nb.file = path.to_str().unwrap().into();
+        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        nb.encryption = Some(EncryptionScheme::default());
+        nb.set_passphrase(Passphrase::new("hunter2".into()));
+        nb.new_entry(Entry::new("Secret.".into(), &nb.dt_format, true))
+            .unwrap();
+        nb.write_all_entries().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(crypto::is_encrypted(&bytes));
+        assert!(!String::from_utf8_lossy(&bytes).contains("Secret."));
+
+        let mut reopened = Notebook::new();
+        reopened.file = nb.file.clone();
+        reopened.dt_format = nb.dt_format.clone();
+        reopened.set_passphrase(Passphrase::new("hunter2".into()));
+        let reopened = reopened.populate_notebook().unwrap();
+        assert_eq!(reopened.entries[0].text, "Secret.");
+        assert!(reopened.encryption.is_some());
+
+        let mut wrong = Notebook::new();
+        wrong.file = nb.file.clone();
+        wrong.set_passphrase(Passphrase::new("hunter3".into()));
+        assert!(wrong.populate_notebook().is_err());
+    }
+
+    #[test]
+    fn test_invalid_dt_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut nb = Notebook::new();
+        nb.file = dir.path().join("notebook.md").to_str().unwrap().into();
+        nb.dt_format = "%Q".into();
+        nb.create().unwrap();
+        let err = nb.clone().populate_notebook().unwrap_err();
+        assert_eq!(err.to_string(), "dt_format '%Q' is not a valid format");
+
+        // Also refused when saving a notebook that was never populated
+        nb.write_new_entry(Some("Dear diary".into())).unwrap();
+        assert!(matches!(nb.save(), Err(Error::DtFormat { .. })));
+    }
+
+    #[test]
+    fn test_encrypted_notebook_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notebook.md");
+        let scheme = EncryptionScheme::default();
+        let passphrase = Passphrase::new("hunter2".into());
+        let bad = "### Not a date\n---\n\nTOP SECRET\n\n";
+        let good = Entry::new("Fine.".into(), "%A %e %B, %Y - %H:%M", true).to_string();
+        let contents = format!("{good}{bad}¶\n");
+        let bytes = crypto::encrypt(&scheme, &passphrase, contents.as_bytes()).unwrap();
+        fs::write(&path, bytes).unwrap();
+
+        let mut nb = Notebook::new();
+        nb.file = path.to_str().unwrap().into();
+        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        nb.recover = true;
+        nb.set_passphrase(passphrase.clone());
+        let mut nb = nb.populate_notebook_with(&mut vec![]).unwrap();
+        nb.save().unwrap();
+
+        // Quarantined blocks stay encrypted at rest
+        let rejects = fs::read(nb.rejects_file()).unwrap();
+        assert!(crypto::is_encrypted(&rejects));
+        assert!(!String::from_utf8_lossy(&rejects).contains("TOP SECRET"));
+        let rejects = crypto::decrypt(&scheme, &passphrase, &rejects).unwrap();
+        assert_eq!(String::from_utf8(rejects).unwrap(), format!("{bad}¶\n"));
+    }
+
+    #[test]
+    fn test_directory_notebook() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("journal");
+        let open = || {
+            let mut nb = Notebook::new();
+            nb.file = root.to_str().unwrap().into();
+            nb.storage = StorageKind::Directory;
+            nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+            nb.create().unwrap();
+            nb.populate_notebook().unwrap()
+        };
+
+        // The single file notebook's entries, moved into the directory
+        let mut nb = open();
+        for e in create_notebook().entries {
+            nb.new_entry(e).unwrap();
+        }
+        assert_eq!(nb.pending, Pending::Append(4));
+        nb.save().unwrap();
+        assert!(root.join("2020/11/20-2016-7b0f37.md").is_file());
+        assert!(root.join("2021/05/13-2217-2c2451.md").is_file());
+
+        let mut nb = open();
+        let ids: Vec<_> = nb.entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, ["7b0f37", "cbc285", "e28369", "2c2451"]);
+        nb.delete_entry(0, false).unwrap();
+        nb.save().unwrap();
+        assert!(!root.join("2020/11/20-2016-7b0f37.md").exists());
+        assert_eq!(open().entries.len(), 3);
+
+        let mut nb = open();
+        nb.undelete_entries(1, &mut vec![]).unwrap();
+        nb.save().unwrap();
+        assert_eq!(open().entries[0].id, "7b0f37");
+        assert!(!nb.trash_file().exists());
+    }
+
+    #[test]
+    fn test_encrypted_directory_notebook() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut nb = Notebook::new();
+        nb.file = dir.path().to_str().unwrap().into();
+        nb.storage = StorageKind::Directory;
+        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        nb.encryption = Some(EncryptionScheme::default());
+        nb.set_passphrase(Passphrase::new("hunter2".into()));
+        let mut nb = nb.populate_notebook().unwrap();
+        nb.new_entry(Entry::new("Secret.".into(), &nb.dt_format, false))
+            .unwrap();
+        nb.save().unwrap();
+
+        let (path, bytes) = nb.storage().read().unwrap().remove(0);
+        assert!(crypto::is_encrypted(&bytes));
+        nb.entries.clear();
+        let nb = nb.populate_notebook().unwrap();
+        assert_eq!(nb.entries[0].text, "Secret.");
+        assert!(path.starts_with(dir.path()));
+
+        // Rewriting leaves the unchanged entry's ciphertext alone
+        nb.write_all_entries().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_encrypted_directory_shares_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let scheme = EncryptionScheme::default();
+        let passphrase = Passphrase::new("hunter2".into());
+        let open = || {
+            let mut nb = Notebook::new();
+            nb.file = dir.path().to_str().unwrap().into();
+            nb.storage = StorageKind::Directory;
+            nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+            nb.encryption = Some(scheme.clone());
+            nb.set_passphrase(passphrase.clone());
+            nb.populate_notebook().unwrap()
+        };
+        let salts = |nb: &Notebook| -> Vec<Vec<u8>> {
+            let files = nb.storage().read().unwrap();
+            files.into_iter().map(|(_, b)| b[8..24].to_vec()).collect()
+        };
+
+        let mut nb = open();
+        for e in create_notebook().entries.into_iter().take(3) {
+            nb.new_entry(e).unwrap();
+        }
+        nb.save().unwrap();
+        let salt = salts(&nb)[0].clone();
+        assert_eq!(salts(&nb), vec![salt.clone(); 3]);
+
+        // An entry sealed with a key of its own is still read, and sealed
+        // with the notebook's key once the notebook is rewritten
+        let old = create_notebook().entries.pop().unwrap();
+        let path = DirectoryStorage::new(dir.path()).entry_path(&old);
+        let bytes = crypto::encrypt(&scheme, &passphrase, old.to_string().as_bytes()).unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, bytes).unwrap();
+        let nb = open();
+        assert_eq!(nb.entries.len(), 4);
+        assert_ne!(salts(&nb)[3], salt);
+
+        nb.write_all_entries().unwrap();
+        assert_eq!(salts(&nb), vec![salt; 4]);
+    }
+
+    fn copy_notebook(dir: &Path, dt_format: &str) -> Notebook {
+        let path = dir.join("notebook.md");
+        fs::copy("data/test.md", &path).unwrap();
+        let mut nb = Notebook::new();
+        nb.file = path.to_str().unwrap().into();
+        nb.dt_format = dt_format.into();
+        nb.populate_notebook().unwrap()
+    }
+
+    #[test]
+    fn test_save_read_only_leaves_file() {
+        let dir = tempfile::tempdir().unwrap();
+        // A different format would change every header if the file were rewritten
+        let mut nb = copy_notebook(dir.path(), "%Y-%m-%d %H:%M");
+        nb.list_entries(&5, &mut vec![], 0, None).unwrap();
+        nb.search("Lupin".into(), None).unwrap();
+        nb.save().unwrap();
+        assert_eq!(
+            fs::read(&nb.file).unwrap(),
+            fs::read("data/test.md").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_save_new_entry_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut nb = copy_notebook(dir.path(), "%Y-%m-%d %H:%M");
+        let e = Entry::new("Appended.".into(), "%Y-%m-%d %H:%M", true);
+        let expected = format!("{}{e}", fs::read_to_string("data/test.md").unwrap());
+        nb.new_entry(e).unwrap();
+        assert_eq!(nb.pending, Pending::Append(1));
+        nb.save().unwrap();
+        assert_eq!(fs::read_to_string(&nb.file).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_save_edit_rewrites() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut nb = copy_notebook(dir.path(), "%Y-%m-%d %H:%M");
+        nb.delete_entry(0, false).unwrap();
+        nb.save().unwrap();
+        let contents = fs::read_to_string(&nb.file).unwrap();
+        assert!(contents.starts_with("### 2020-11-21 21:14 {#cbc285}\n"));
+    }
+
+    #[test]
+    fn test_find_entry() {
+        let nb = create_notebook();
+        assert_eq!(nb.find(&EntryRef::Index(2)).unwrap(), 2);
+        assert_eq!(nb.find(&EntryRef::FromEnd(1)).unwrap(), 3);
+        assert_eq!(nb.find(&EntryRef::Id("cbc285".into())).unwrap(), 1);
+        assert!(nb.find(&EntryRef::Index(4)).is_err());
+        assert!(nb.find(&EntryRef::FromEnd(5)).is_err());
+        assert!(nb.find(&EntryRef::Id("ffffff".into())).is_err());
+    }
+
+    #[test]
+    fn test_ids_survive_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut nb = copy_notebook(dir.path(), "%A %e %B, %Y - %H:%M");
+        let id = EntryRef::Id(nb.entries[2].id.clone());
+        nb.delete_entry(0, false).unwrap();
+        nb.save().unwrap();
+
+        let mut nb = Notebook::new();
+        nb.file = dir.path().join("notebook.md").to_str().unwrap().into();
+        nb.dt_format = "%A %e %B, %Y - %H:%M".into();
+        let nb = nb.populate_notebook().unwrap();
+        let mut stdout = vec![];
+        nb.read_entry(&nb.find(&id).unwrap(), &mut stdout).unwrap();
+        assert!(stdout.starts_with("### Thursday 22 April, 2021 - 16:14".as_bytes()));
+    }
+
+    #[test]
+    fn test_duplicate_ids_are_reassigned() {
+        let mut nb = create_notebook();
+        let e = nb.entries[0].clone();
+        nb.new_entry(e).unwrap();
+        assert_ne!(nb.entries[4].id, nb.entries[0].id);
+    }
+
     #[test]
     fn test_new_entry() {
-        let e = Entry::new("Testing this entry".into(), "%A %e %B, %Y - %H:%M");
+        let e = Entry::new("Testing this entry".into(), "%A %e %B, %Y - %H:%M", true);
         let mut nb = create_notebook();
         nb.new_entry(e).unwrap();
         assert_eq!(nb.entries.len(), 5);
         assert_eq!(nb.entries[4].text, "Testing this entry");
     }
 
+    #[test]
+    fn test_write_new_entry() {
+        let mut nb = create_notebook();
+        nb.sentiment = false;
+        let nb = nb.run_command(Args::New(Some("Written.".into()))).unwrap();
+        assert_eq!(nb.entries[4].text, "Written.");
+    }
+
     #[test]
     fn test_list_one_entry() {
         let mut stdout = vec![];
         let nb = create_notebook();
-        nb.list_entries(&1, &mut stdout, 0).unwrap();
+        nb.list_entries(&1, &mut stdout, 0, None).unwrap();
         assert!(stdout.starts_with(
-            "\u{1b}[1m3\u{1b}[0m: \u{1b}[1m2021-05-13 22:17:00\u{1b}[0m\tA".as_bytes()
+            "\u{1b}[1m3\u{1b}[0m \u{1b}[2m2c2451\u{1b}[0m: \u{1b}[1m2021-05-13 22:17:00\u{1b}[0m\tA".as_bytes()
         ));
         assert!(stdout.ends_with("happened: Lupin is disch…\n".as_bytes()));
     }
@@ -282,13 +1173,57 @@ mod test_notebook {
     fn test_list_one_entry_verbose() {
         let mut stdout = vec![];
         let nb = create_notebook();
-        nb.list_entries(&1, &mut stdout, 1).unwrap();
+        nb.list_entries(&1, &mut stdout, 1, None).unwrap();
         assert!(stdout.starts_with(
-            "\u{1b}[1m3\u{1b}[0m: \u{1b}[1m2021-05-13 22:17:00\u{1b}[0m\tA".as_bytes()
+            "\u{1b}[1m3\u{1b}[0m \u{1b}[2m2c2451\u{1b}[0m: \u{1b}[1m2021-05-13 22:17:00\u{1b}[0m\tA".as_bytes()
         ));
         assert!(stdout.ends_with("Mr. Perkupp’s office; and I scarcely …\n".as_bytes()));
     }
 
+    fn create_tagged_notebook() -> Notebook {
+        let mut nb = create_notebook();
+        for t in [
+            "Lupin at the #office again.",
+            "#Office party with @Carrie.",
+            "Quiet day #home, Lupin out.",
+        ] {
+            nb.new_entry(Entry::new(t.into(), "%A %e %B, %Y - %H:%M", false))
+                .unwrap();
+        }
+        nb
+    }
+
+    #[test]
+    fn test_list_entries_by_tag() {
+        let mut stdout = vec![];
+        let nb = create_tagged_notebook();
+        nb.list_entries(&5, &mut stdout, 0, Some("office")).unwrap();
+        let out = String::from_utf8(stdout).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("Lupin at the #office again."));
+        assert!(out.contains("#Office party with @Carrie."));
+    }
+
+    #[test]
+    fn test_list_tags() {
+        let mut stdout = vec![];
+        let nb = create_tagged_notebook();
+        nb.list_tags(&mut stdout).unwrap();
+        assert_eq!(
+            stdout,
+            "\u{1b}[1m#office\u{1b}[0m\t2\n\u{1b}[1m#home\u{1b}[0m\t1\n\u{1b}[1m@Carrie\u{1b}[0m\t1\n"
+                .as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_search_by_tag() {
+        let mut nb = create_tagged_notebook();
+        nb.search("Lupin".into(), Some("#office")).unwrap();
+        assert_eq!(nb.search_result.len(), 1);
+        assert_eq!(nb.search_result[0].entry_idx, 4);
+    }
+
     #[test]
     fn test_read_first_entry() {
         let mut stdout = vec![];
@@ -302,8 +1237,10 @@ mod test_notebook {
     fn test_read_outside_upper_bound() {
         let mut stdout = vec![];
         let nb = create_notebook();
-        nb.read_entry(&4, &mut stdout).unwrap();
-        assert_eq!(stdout, b"No such entry.\n");
+        let err = nb.read_entry(&4, &mut stdout).unwrap_err();
+        assert!(matches!(err, Error::NotFound { .. }));
+        assert_eq!(err.to_string(), "no entry '4' in 'data/test.md'");
+        assert!(stdout.is_empty());
     }
 
     #[test]
@@ -325,45 +1262,206 @@ mod test_notebook {
         assert_eq!(nb.entries.len(), 3);
     }
 
+    #[test]
+    fn test_delete_declined() {
+        let mut out = vec![];
+        let mut nb = create_notebook();
+        nb.delete_entry_with(1, Some(&b"\n"[..]), &mut out).unwrap();
+        assert_eq!(nb.entries.len(), 4);
+        assert_eq!(nb.pending, Pending::Nothing);
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("2020-11-21 21:14:00"));
+        assert!(out.contains("Delete entry cbc285? (y/N) "));
+        assert!(out.ends_with("Entry not deleted\n"));
+    }
+
+    #[test]
+    fn test_delete_confirmed() {
+        let mut out = vec![];
+        let mut nb = create_notebook();
+        nb.delete_entry_with(1, Some(&b"yes\n"[..]), &mut out)
+            .unwrap();
+        assert_eq!(nb.entries.len(), 3);
+        assert_eq!(nb.entries[1].id, "e28369");
+        assert!(out.ends_with(b"Deleted entry cbc285, `nb undelete` restores it\n"));
+    }
+
+    #[test]
+    fn test_delete_and_undelete() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut nb = copy_notebook(dir.path(), "%A %e %B, %Y - %H:%M");
+        let original = fs::read_to_string(&nb.file).unwrap();
+        nb.delete_entry(1, false).unwrap();
+        nb.delete_entry(2, false).unwrap();
+        nb.save().unwrap();
+        assert_eq!(nb.entries.len(), 2);
+        let trash = fs::read_to_string(nb.trash_file()).unwrap();
+        assert!(trash.starts_with("### Saturday 21 November, 2020 - 21:14 {#cbc285}"));
+
+        // Restored entries go back to their places, with their IDs
+        let mut stdout = vec![];
+        nb.undelete_entries(5, &mut stdout).unwrap();
+        assert_eq!(stdout, b"Restored entry cbc285\nRestored entry 2c2451\n");
+        nb.save().unwrap();
+        let restored = fs::read_to_string(&nb.file).unwrap();
+        assert_eq!(restored.lines().count(), original.lines().count());
+        assert_eq!(nb.find(&EntryRef::Id("cbc285".into())).unwrap(), 1);
+        assert_eq!(nb.find(&EntryRef::Id("2c2451".into())).unwrap(), 3);
+        assert!(!nb.trash_file().exists());
+
+        let mut stdout = vec![];
+        nb.undelete_entries(1, &mut stdout).unwrap();
+        assert_eq!(stdout, b"Nothing to restore\n");
+        assert_eq!(nb.pending, Pending::Nothing);
+    }
+
+    #[test]
+    fn test_summary_splits_on_char_boundary() {
+        let mut stdout = vec![];
+        let mut nb = Notebook::new();
+        nb.new_entry(Entry::new("é".repeat(60), "%Y-%m-%d %H:%M", false))
+            .unwrap();
+        nb.list_entries(&1, &mut stdout, 0, None).unwrap();
+        assert!(String::from_utf8(stdout)
+            .unwrap()
+            .ends_with(&format!("\t{}…\n", "é".repeat(25))));
+    }
+
+    #[test]
+    fn test_out_of_range_commands() {
+        let mut nb = create_notebook();
+        assert!(matches!(
+            nb.delete_entry(4, false),
+            Err(Error::NotFound { .. })
+        ));
+        assert!(matches!(nb.edit_entry(4), Err(Error::NotFound { .. })));
+        assert_eq!(nb.entries.len(), 4);
+        assert_eq!(nb.pending, Pending::Nothing);
+    }
+
+    #[test]
+    fn test_search_bad_pattern() {
+        let mut nb = create_notebook();
+        let err = nb.search("(unclosed".into(), None).unwrap_err();
+        assert_eq!(err.to_string(), "invalid search pattern '(unclosed'");
+    }
+
+    #[test]
+    fn test_delete_last_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut nb = copy_notebook(dir.path(), "%A %e %B, %Y - %H:%M");
+        for _ in 0..4 {
+            nb.delete_entry(0, false).unwrap();
+        }
+        nb.save().unwrap();
+        assert_eq!(fs::read_to_string(&nb.file).unwrap(), "");
+    }
+
+    #[test]
+    fn test_rescore_entries() {
+        let mut stdout = vec![];
+        let mut nb = create_notebook();
+        nb.sentiment = false;
+        nb.rescore_entries(&mut stdout).unwrap();
+        nb.read_entry(&0, &mut stdout).unwrap();
+        let out = String::from_utf8(stdout).unwrap();
+        assert!(out.starts_with("Rescored 4 entries\n"));
+        assert!(!out.contains("####"));
+    }
+
     #[test]
     fn test_search_word_single_result() {
         let mut nb = create_notebook();
-        nb.search("brandy".into()).unwrap();
+        nb.search("brandy".into(), None).unwrap();
         assert_eq!(nb.search_result.len(), 1);
     }
 
     #[test]
     fn test_search_phrase_single_result() {
         let mut nb = create_notebook();
-        nb.search("poisoned by some lobster".into()).unwrap();
+        nb.search("poisoned by some lobster".into(), None).unwrap();
         assert_eq!(nb.search_result.len(), 1);
     }
 
     #[test]
     fn test_search_multiple_results() {
         let mut nb = create_notebook();
-        nb.search("Lupin".into()).unwrap();
+        nb.search("Lupin".into(), None).unwrap();
         assert_eq!(nb.search_result.len(), 3);
     }
 
     #[test]
     fn test_search_zero_results() {
         let mut nb = create_notebook();
-        nb.search("zebra".into()).unwrap();
+        nb.search("zebra".into(), None).unwrap();
         assert_eq!(nb.search_result.len(), 0);
     }
 
     #[test]
     fn test_search_daterange() {
-        let _nb: Notebook = create_notebook();
+        let mut nb = create_notebook();
+        nb.search("Lupin".into(), None).unwrap();
+        nb.filter_search_results(&"2021-01-01..2021-06-01".parse().unwrap())
+            .unwrap();
+        assert_eq!(nb.search_result.len(), 1);
+        assert_eq!(nb.search_result[0].entry_idx, 3);
+    }
+
+    #[test]
+    fn test_search_daterange_open_ended() {
+        let mut nb = create_notebook();
+        nb.search("Lupin".into(), None).unwrap();
+        nb.filter_search_results(&"..2020-11-20".parse().unwrap())
+            .unwrap();
+        assert_eq!(nb.search_result.len(), 1);
+        assert_eq!(nb.search_result[0].entry_idx, 0);
+    }
+
+    fn date(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_date_search_closest() {
+        let mut stdout = vec![];
+        let nb = create_notebook();
+        nb.date_search(
+            &date("2020-11-21 00:00"),
+            &DateWindow::Closest(2),
+            &mut stdout,
+            0,
+        )
+        .unwrap();
+        let out = String::from_utf8(stdout).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("2020-11-20 20:16:00"));
+        assert!(out.contains("2020-11-21 21:14:00"));
+    }
+
+    #[test]
+    fn test_date_search_days() {
+        let mut stdout = vec![];
+        let nb = create_notebook();
+        nb.date_search(
+            &date("2021-05-10 12:00"),
+            &DateWindow::Days(3),
+            &mut stdout,
+            0,
+        )
+        .unwrap();
+        assert!(stdout.starts_with(
+            "\u{1b}[1m3\u{1b}[0m \u{1b}[2m2c2451\u{1b}[0m: \u{1b}[1m2021-05-13 22:17:00\u{1b}[0m\tA".as_bytes()
+        ));
+        assert_eq!(String::from_utf8(stdout).unwrap().lines().count(), 1);
     }
 
     #[test]
     fn test_search_correct_location() {
         let mut nb = create_notebook();
-        nb.search("Crowbillon".into()).unwrap();
+        nb.search("Crowbillon".into(), None).unwrap();
         assert_eq!(nb.search_result[0].location[0], "Crowbillon");
-        nb.search("’".into()).unwrap();
+        nb.search("’".into(), None).unwrap();
         assert_eq!(nb.search_result[1].location[1], "’");
     }
 
@@ -371,7 +1469,7 @@ mod test_notebook {
     fn test_search_output() {
         let mut stdout = vec![];
         let mut nb = create_notebook();
-        nb.search("Crowbillon".into())
+        nb.search("Crowbillon".into(), None)
             .unwrap()
             .output_search_results(&mut stdout)
             .unwrap();
@@ -0,0 +1,353 @@
+use crate::{
+    config::check_create_file,
+    entry::TERMINATOR,
+    error::{Context, Error},
+    write_atomic, Entry,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Turns an entry's text into the bytes written for it, encrypting them if
+/// the notebook is encrypted.
+pub type Encoder<'a> = &'a dyn Fn(String, &Path) -> Result<Vec<u8>, Error>;
+
+/// Turns the bytes written for an entry back into its text, or `None` if
+/// they weren't written the way the notebook now encodes them.
+pub type Decoder<'a> = &'a dyn Fn(Vec<u8>, &Path) -> Option<String>;
+
+/// How a notebook's entries are laid out on disk, set with `storage` in the
+/// config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// Every entry in one Markdown file.
+    #[default]
+    File,
+    /// Each entry in its own file, as `YYYY/MM/DD-HHMM-<id>.md`.
+    Directory,
+}
+
+impl StorageKind {
+    /// The backend for a notebook kept at `path`.
+    pub fn open(self, path: &Path) -> Box<dyn Storage> {
+        match self {
+            StorageKind::File => Box::new(FileStorage::new(path)),
+            StorageKind::Directory => Box::new(DirectoryStorage::new(path)),
+        }
+    }
+}
+
+/// Reads and writes a notebook's entries, leaving parsing, encryption and
+/// everything else to `Notebook`.
+pub trait Storage {
+    /// Creates an empty notebook if there isn't one yet.
+    fn create(&self) -> Result<(), Error>;
+
+    /// Each file holding entries, with its contents, oldest entries first.
+    fn read(&self) -> Result<Vec<(PathBuf, Vec<u8>)>, Error>;
+
+    /// Whether new entries can be added without rewriting the others, given
+    /// the contents of the last file read.
+    fn appendable(&self, last: Option<&str>, encrypted: bool) -> bool;
+
+    /// Adds entries after those already written.
+    fn append(&self, entries: &[Entry], encode: Encoder) -> Result<(), Error>;
+
+    /// Replaces everything written with `entries`, using `decode` to tell
+    /// which of them are already written as they are.
+    fn write_all(&self, entries: &[Entry], encode: Encoder, decode: Decoder) -> Result<(), Error>;
+}
+
+/// A notebook kept as a single file, entries separated by terminator lines.
+#[derive(Clone, Debug)]
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: &Path) -> FileStorage {
+        FileStorage {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn create(&self) -> Result<(), Error> {
+        check_create_file(&self.path.to_string_lossy().into())?;
+        Ok(())
+    }
+
+    fn read(&self) -> Result<Vec<(PathBuf, Vec<u8>)>, Error> {
+        let bytes =
+            fs::read(&self.path).context(format!("unable to open '{}'", self.path.display()))?;
+        Ok(vec![(self.path.clone(), bytes)])
+    }
+
+    fn appendable(&self, last: Option<&str>, encrypted: bool) -> bool {
+        // Entries can only be appended after a cleanly terminated one, and
+        // an encrypted file has to be sealed as a whole
+        !encrypted && last.is_none_or(|c| c.is_empty() || c.ends_with(&format!("{TERMINATOR}\n")))
+    }
+
+    fn append(&self, entries: &[Entry], _encode: Encoder) -> Result<(), Error> {
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .context(format!(
+                "unable to open or create '{}'",
+                self.path.display()
+            ))?;
+
+        for e in entries {
+            file.write_all(e.to_string().as_bytes())
+                .context(format!("unable to write to '{}'", self.path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn write_all(&self, entries: &[Entry], encode: Encoder, _decode: Decoder) -> Result<(), Error> {
+        // Replace the notebook atomically, encrypted notebooks are only
+        // ever written as ciphertext.
+        let contents: String = entries.iter().map(|e| e.to_string()).collect();
+        write_atomic(&self.path, &encode(contents, &self.path)?)
+    }
+}
+
+/// A notebook kept as a directory with a file per entry, so that a years
+/// long journal is never rewritten as a whole and merges cleanly.
+#[derive(Clone, Debug)]
+pub struct DirectoryStorage {
+    root: PathBuf,
+}
+
+impl DirectoryStorage {
+    pub fn new(root: &Path) -> DirectoryStorage {
+        DirectoryStorage {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Where an entry is kept, `YYYY/MM/DD-HHMM-<id>.md` under the root.
+    pub fn entry_path(&self, entry: &Entry) -> PathBuf {
+        let name = entry.timestamp.format("%Y/%m/%d-%H%M");
+        self.root.join(format!("{name}-{}.md", entry.id))
+    }
+
+    /// Every entry file under the root, sorted by path and so by date.
+    fn entry_files(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut files = vec![];
+        find_entry_files(&self.root, &mut files)
+            .context(format!("unable to open '{}'", self.root.display()))?;
+        files.retain(|f| f.strip_prefix(&self.root).is_ok_and(is_entry_file));
+        files.sort();
+        Ok(files)
+    }
+
+    fn write_entry(
+        &self,
+        entry: &Entry,
+        encode: Encoder,
+        decode: Option<Decoder>,
+    ) -> Result<PathBuf, Error> {
+        let path = self.entry_path(entry);
+        let contents = entry.to_string();
+        // Unchanged entries are left alone, keeping their history clean.
+        // Encrypted ones are compared decrypted, as ciphertext never repeats
+        if let (Some(decode), Ok(bytes)) = (decode, fs::read(&path)) {
+            if decode(bytes, &path).is_some_and(|c| c == contents) {
+                return Ok(path);
+            }
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(format!("unable to create '{}'", dir.display()))?;
+        }
+        write_atomic(&path, &encode(contents, &path)?)?;
+        Ok(path)
+    }
+}
+
+impl Storage for DirectoryStorage {
+    fn create(&self) -> Result<(), Error> {
+        fs::create_dir_all(&self.root).context(format!(
+            "unable to open or create '{}'",
+            self.root.display()
+        ))
+    }
+
+    fn read(&self) -> Result<Vec<(PathBuf, Vec<u8>)>, Error> {
+        self.entry_files()?
+            .into_iter()
+            .map(|path| {
+                let bytes = fs::read(&path).context(format!("unable to open '{}'", path.display()));
+                bytes.map(|b| (path, b))
+            })
+            .collect()
+    }
+
+    fn appendable(&self, _last: Option<&str>, _encrypted: bool) -> bool {
+        true
+    }
+
+    fn append(&self, entries: &[Entry], encode: Encoder) -> Result<(), Error> {
+        for e in entries {
+            self.write_entry(e, encode, None)?;
+        }
+        Ok(())
+    }
+
+    fn write_all(&self, entries: &[Entry], encode: Encoder, decode: Decoder) -> Result<(), Error> {
+        let existing = self.entry_files()?;
+        let mut kept = HashSet::new();
+        for e in entries {
+            kept.insert(self.write_entry(e, encode, Some(decode))?);
+        }
+
+        for path in existing.iter().filter(|p| !kept.contains(*p)) {
+            fs::remove_file(path).context(format!("unable to remove '{}'", path.display()))?;
+            // Month and year directories go once they are empty
+            for dir in path.ancestors().skip(1).take(2) {
+                if dir == self.root || fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects the entry files under `dir`, skipping hidden files and
+/// directories.
+fn find_entry_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for item in fs::read_dir(dir)? {
+        let path = item?.path();
+        if path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            find_entry_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `path`, relative to the root, is laid out as
+/// `YYYY/MM/DD-HHMM-<id>.md`, so that a README or notes kept alongside the
+/// entries aren't read as entries.
+fn is_entry_file(path: &Path) -> bool {
+    let digits = |s: &str, n| s.len() == n && s.bytes().all(|b| b.is_ascii_digit());
+    let parts: Vec<_> = path.iter().map(|p| p.to_string_lossy()).collect();
+    let [year, month, name] = parts.as_slice() else {
+        return false;
+    };
+
+    let name = name.strip_suffix(".md").unwrap_or_default();
+    let mut fields = name.splitn(3, '-');
+    digits(year, 4)
+        && digits(month, 2)
+        && fields.next().is_some_and(|d| digits(d, 2))
+        && fields.next().is_some_and(|t| digits(t, 4))
+        && fields.next().is_some_and(|id| !id.is_empty())
+}
+
+#[cfg(test)]
+mod test_storage {
+    use super::*;
+    use crate::entry::DEFAULT_DT_FORMAT;
+
+    fn plain(contents: String, _: &Path) -> Result<Vec<u8>, Error> {
+        Ok(contents.into_bytes())
+    }
+
+    fn unplain(bytes: Vec<u8>, _: &Path) -> Option<String> {
+        String::from_utf8(bytes).ok()
+    }
+
+    fn entry(text: &str, timestamp: &str) -> Entry {
+        let mut e = Entry::new(text.into(), DEFAULT_DT_FORMAT, false);
+        e.timestamp = timestamp.parse().unwrap();
+        e
+    }
+
+    #[test]
+    fn test_directory_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = DirectoryStorage::new(dir.path());
+        let e = entry("Dear diary", "2021-05-13T22:17:00");
+        storage.append(std::slice::from_ref(&e), &plain).unwrap();
+
+        let path = dir.path().join(format!("2021/05/13-2217-{}.md", e.id));
+        assert_eq!(fs::read_to_string(&path).unwrap(), e.to_string());
+        assert_eq!(
+            storage.read().unwrap(),
+            [(path, e.to_string().into_bytes())]
+        );
+    }
+
+    #[test]
+    fn test_directory_write_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = DirectoryStorage::new(dir.path());
+        let old = entry("Old", "2020-11-20T20:16:00");
+        let new = entry("New", "2021-05-13T22:17:00");
+        storage.append(&[old.clone(), new.clone()], &plain).unwrap();
+
+        storage
+            .write_all(std::slice::from_ref(&new), &plain, &unplain)
+            .unwrap();
+        let files: Vec<_> = storage.read().unwrap().into_iter().map(|f| f.0).collect();
+        assert_eq!(files, [storage.entry_path(&new)]);
+        assert!(!dir.path().join("2020").exists());
+    }
+
+    #[test]
+    fn test_directory_skips_hidden_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/notes.md"), "").unwrap();
+        fs::write(dir.path().join("notes.txt"), "").unwrap();
+        assert!(DirectoryStorage::new(dir.path()).read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_directory_skips_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("2021/05")).unwrap();
+        fs::write(dir.path().join("README.md"), "").unwrap();
+        fs::write(dir.path().join("2021/notes.md"), "").unwrap();
+        fs::write(dir.path().join("2021/05/13-2217.md"), "").unwrap();
+        fs::write(dir.path().join("2021/05/13-2217-abc.md"), "").unwrap();
+
+        let files: Vec<_> = DirectoryStorage::new(dir.path())
+            .read()
+            .unwrap()
+            .into_iter()
+            .map(|f| f.0)
+            .collect();
+        assert_eq!(files, [dir.path().join("2021/05/13-2217-abc.md")]);
+    }
+
+    #[test]
+    fn test_file_appendable() {
+        let storage = FileStorage::new(Path::new("notebook.md"));
+        assert!(storage.appendable(None, false));
+        assert!(storage.appendable(Some(""), false));
+        assert!(storage.appendable(Some("...\n¶\n"), false));
+        assert!(!storage.appendable(Some("...\n"), false));
+        assert!(!storage.appendable(Some(""), true));
+    }
+}
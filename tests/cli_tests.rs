@@ -28,6 +28,28 @@ fn test_read_last() {
         .stdout_eq(file!["cmd/test_read_last.stdout"]);
 }
 
+#[test]
+fn test_read_latest() {
+    Command::new(cargo_bin!("nb"))
+        .arg("-c")
+        .arg("data/test_config.toml")
+        .arg("-r")
+        .arg("-1")
+        .assert()
+        .stdout_eq(file!["cmd/test_read_last.stdout"]);
+}
+
+#[test]
+fn test_read_by_id() {
+    Command::new(cargo_bin!("nb"))
+        .arg("-c")
+        .arg("data/test_config.toml")
+        .arg("-r")
+        .arg("7b0f37")
+        .assert()
+        .stdout_eq(file!["cmd/test_read_first.stdout"]);
+}
+
 #[test]
 fn test_list_all() {
     Command::new(cargo_bin!("nb"))
@@ -78,3 +100,77 @@ fn test_search_regex() {
         .assert()
         .stdout_eq(file!["cmd/test_search_regex.stdout"]);
 }
+
+#[test]
+fn test_search_date_range() {
+    Command::new(cargo_bin!("nb"))
+        .arg("-c")
+        .arg("data/test_config.toml")
+        .arg("-s")
+        .arg("Lupin")
+        .arg("-d")
+        .arg("2021-01-01..2021-06-01")
+        .assert()
+        .stdout_eq(file!["cmd/test_search_date_range.stdout"]);
+}
+
+#[test]
+fn test_date_search() {
+    Command::new(cargo_bin!("nb"))
+        .arg("-c")
+        .arg("data/test_config.toml")
+        .arg("-d")
+        .arg("13 May 2021")
+        .arg("-k")
+        .arg("2")
+        .assert()
+        .stdout_eq(file!["cmd/test_date_search.stdout"]);
+}
+
+#[test]
+fn test_read_missing_entry() {
+    Command::new(cargo_bin!("nb"))
+        .arg("-c")
+        .arg("data/test_config.toml")
+        .arg("-r")
+        .arg("99")
+        .assert()
+        .code(1)
+        .stdout_eq("")
+        .stderr_eq("no entry '99' in 'data/test.md'\n");
+}
+
+#[test]
+fn test_delete_declined() {
+    Command::new(cargo_bin!("nb"))
+        .arg("-c")
+        .arg("data/test_config.toml")
+        .arg("-X")
+        .arg("0")
+        .stdin("n\n")
+        .assert()
+        .success()
+        .stdout_eq(file!["cmd/test_delete_declined.stdout"]);
+}
+
+#[test]
+fn test_read_file_without_config() {
+    Command::new(cargo_bin!("nb"))
+        .env("NOTEBOOK_RS_CONFIG", "data/missing_config.toml")
+        .arg("--file")
+        .arg("data/test.md")
+        .arg("-r")
+        .arg("0")
+        .assert()
+        .stdout_eq(file!["cmd/test_read_first.stdout"]);
+}
+
+#[test]
+fn test_config_from_env() {
+    Command::new(cargo_bin!("nb"))
+        .env("NOTEBOOK_RS_CONFIG", "data/test_config.toml")
+        .arg("-r")
+        .arg("3")
+        .assert()
+        .stdout_eq(file!["cmd/test_read_last.stdout"]);
+}